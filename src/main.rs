@@ -1,4 +1,14 @@
-use std::io;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::process;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 /*
  * Simple math lisp interpreter in Rust.
@@ -14,11 +24,409 @@ use std::io;
  * Number(22)
  */
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub enum LispExpr {
     Number(i64),
+    Bool(bool),
     Symbol(String),
-    List(Vec<LispExpr>),
+    List(Rc<Vec<LispExpr>>),
+    Promise(Rc<RefCell<PromiseState>>),
+    Hash(Rc<RefCell<HashMap<HashKey, LispExpr>>>),
+    Record {
+        type_name: String,
+        fields: Vec<(String, LispExpr)>,
+    },
+}
+
+impl LispExpr {
+    /*
+     * Lists are reference counted, so cloning a list value or passing it to a
+     * builtin shares the elements instead of copying them.
+     */
+    pub fn list(values: Vec<LispExpr>) -> LispExpr {
+        LispExpr::List(Rc::new(values))
+    }
+
+    pub fn nil() -> LispExpr {
+        LispExpr::list(Vec::new())
+    }
+
+    /*
+     * Every value except `false` counts as true in a condition.
+     */
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, LispExpr::Bool(false))
+    }
+
+    /*
+     * Formats the expression like `{:?}`, but with integers written in the given
+     * base (2, 8, 10 or 16). Promises only show whether they have been forced,
+     * as a forced stream can refer back to itself. Hash tables show their
+     * entries sorted by key, and one that contains itself shows `Hash(..)` in
+     * place of the inner reference.
+     */
+    pub fn format_with_base(&self, base: u32) -> String {
+        let mut output = String::new();
+        let mut layout = BaseLayout {
+            base,
+            open: Vec::new(),
+        };
+
+        write_layout(&mut output, self, &mut layout).expect("writing to a String cannot fail");
+        output
+    }
+
+    /*
+     * Formats the expression as lisp source, starting at column `indent`. Lists
+     * that fit within `PRETTY_WIDTH` stay on one line; longer ones put each
+     * argument on its own line, indented two spaces past the opening paren.
+     */
+    pub fn pretty(&self, indent: usize) -> String {
+        let flat = self.to_string();
+
+        match self {
+            LispExpr::List(values) if values.len() > 1 && indent + flat.len() > PRETTY_WIDTH => {
+                let child_indent = indent + 2;
+                let mut output = format!("({}", values[0].pretty(indent + 1));
+
+                for value in &values[1..] {
+                    output.push('\n');
+                    output.push_str(&" ".repeat(child_indent));
+                    output.push_str(&value.pretty(child_indent));
+                }
+
+                output.push(')');
+                output
+            }
+            _ => flat,
+        }
+    }
+}
+
+const PRETTY_WIDTH: usize = 60;
+
+/*
+ * Structural equality, as used by `assert-equal` and `assoc`. Promises and hash
+ * tables are only equal to themselves, since comparing what they hold could
+ * recurse forever through a stream or table that refers back to itself.
+ */
+impl PartialEq for LispExpr {
+    fn eq(&self, other: &LispExpr) -> bool {
+        match (self, other) {
+            (LispExpr::Number(left), LispExpr::Number(right)) => left == right,
+            (LispExpr::Bool(left), LispExpr::Bool(right)) => left == right,
+            (LispExpr::Symbol(left), LispExpr::Symbol(right)) => left == right,
+            (LispExpr::List(left), LispExpr::List(right)) => left == right,
+            (LispExpr::Promise(left), LispExpr::Promise(right)) => Rc::ptr_eq(left, right),
+            (LispExpr::Hash(left), LispExpr::Hash(right)) => Rc::ptr_eq(left, right),
+            (
+                LispExpr::Record {
+                    type_name: left_type,
+                    fields: left_fields,
+                },
+                LispExpr::Record {
+                    type_name: right_type,
+                    fields: right_fields,
+                },
+            ) => left_type == right_type && left_fields == right_fields,
+            _ => false,
+        }
+    }
+}
+
+/*
+ * Matches the derived format, except that hash tables hide their entries: a
+ * table can hold itself, which `{:?}` would follow forever.
+ */
+impl fmt::Debug for LispExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_layout(f, self, &mut DebugLayout)
+    }
+}
+
+/*
+ * Writes the expression as lisp source on a single line.
+ */
+impl fmt::Display for LispExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_layout(f, self, &mut SourceLayout)
+    }
+}
+
+/*
+ * Lists and records are freed through a work list rather than recursively, so
+ * dropping a value nested thousands of lists deep can't overflow the stack.
+ * Only lists this value owns outright are taken apart; shared ones just lose a
+ * reference.
+ */
+impl Drop for LispExpr {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.take_children(&mut pending);
+
+        while let Some(mut expr) = pending.pop() {
+            expr.take_children(&mut pending);
+        }
+    }
+}
+
+impl LispExpr {
+    fn take_children(&mut self, pending: &mut Vec<LispExpr>) {
+        match self {
+            LispExpr::List(values) => {
+                if let Some(values) = Rc::get_mut(values) {
+                    pending.append(values);
+                }
+            }
+            LispExpr::Record { fields, .. } => {
+                pending.extend(fields.drain(..).map(|(_, value)| value));
+            }
+            _ => {}
+        }
+    }
+}
+
+/*
+ * The formatters below write a value by expanding it into pieces on an explicit
+ * stack rather than by recursion, so a value nested thousands of lists deep can
+ * still be printed. A layout says how a single value breaks down into text and
+ * the values written inside it.
+ */
+enum Piece {
+    Text(String),
+    Value(LispExpr),
+    // Ends a hash table's entries, for layouts that track which are open.
+    Leave,
+}
+
+trait Layout {
+    fn expand(&mut self, expr: &LispExpr) -> Vec<Piece>;
+
+    fn leave(&mut self) {}
+}
+
+fn write_layout(out: &mut dyn fmt::Write, expr: &LispExpr, layout: &mut dyn Layout) -> fmt::Result {
+    let mut pending = vec![Piece::Value(expr.clone())];
+
+    while let Some(piece) = pending.pop() {
+        match piece {
+            Piece::Text(text) => out.write_str(&text)?,
+            Piece::Value(value) => pending.extend(layout.expand(&value).into_iter().rev()),
+            Piece::Leave => layout.leave(),
+        }
+    }
+
+    Ok(())
+}
+
+fn text(text: &str) -> Piece {
+    Piece::Text(text.to_string())
+}
+
+// `open`, then the values with `separator` between them, then `close`.
+fn separated(open: &str, values: &[LispExpr], separator: &str, close: &str) -> Vec<Piece> {
+    let mut pieces = vec![text(open)];
+
+    for (index, value) in values.iter().enumerate() {
+        if index > 0 {
+            pieces.push(text(separator));
+        }
+
+        pieces.push(Piece::Value(value.clone()));
+    }
+
+    pieces.push(text(close));
+    pieces
+}
+
+struct SourceLayout;
+
+impl Layout for SourceLayout {
+    fn expand(&mut self, expr: &LispExpr) -> Vec<Piece> {
+        match expr {
+            LispExpr::Number(number) => vec![Piece::Text(number.to_string())],
+            LispExpr::Bool(value) => vec![Piece::Text(value.to_string())],
+            LispExpr::Symbol(symbol) => vec![text(symbol)],
+            LispExpr::List(values) => separated("(", values, " ", ")"),
+            LispExpr::Promise(_) => vec![text("#<promise>")],
+            LispExpr::Hash(_) => vec![text("#<hash-table>")],
+            LispExpr::Record { type_name, fields } => {
+                let mut pieces = vec![Piece::Text(format!("#<{}", type_name))];
+
+                for (name, value) in fields {
+                    pieces.push(Piece::Text(format!(" {}=", name)));
+                    pieces.push(Piece::Value(value.clone()));
+                }
+
+                pieces.push(text(">"));
+                pieces
+            }
+        }
+    }
+}
+
+struct DebugLayout;
+
+impl Layout for DebugLayout {
+    fn expand(&mut self, expr: &LispExpr) -> Vec<Piece> {
+        match expr {
+            LispExpr::Number(number) => vec![Piece::Text(format!("Number({})", number))],
+            LispExpr::Bool(value) => vec![Piece::Text(format!("Bool({})", value))],
+            LispExpr::Symbol(symbol) => vec![Piece::Text(format!("Symbol({:?})", symbol))],
+            LispExpr::List(values) => separated("List([", values, ", ", "])"),
+            LispExpr::Promise(promise) => match promise.try_borrow() {
+                Ok(state) => match &*state {
+                    PromiseState::Delayed(expr) => vec![
+                        text("Promise(RefCell { value: Delayed("),
+                        Piece::Value(expr.clone()),
+                        text(") })"),
+                    ],
+                    PromiseState::Forced(_) => vec![text("Promise(RefCell { value: Forced(..) })")],
+                },
+                Err(_) => vec![text("Promise(RefCell { value: <borrowed> })")],
+            },
+            LispExpr::Hash(_) => vec![text("Hash(..)")],
+            LispExpr::Record { type_name, fields } => record_pieces(type_name, fields),
+        }
+    }
+}
+
+fn record_pieces(type_name: &str, fields: &[(String, LispExpr)]) -> Vec<Piece> {
+    let mut pieces = vec![Piece::Text(format!(
+        "Record {{ type_name: {:?}, fields: [",
+        type_name
+    ))];
+
+    for (index, (name, value)) in fields.iter().enumerate() {
+        let separator = if index > 0 { ", " } else { "" };
+
+        pieces.push(Piece::Text(format!("{}({:?}, ", separator, name)));
+        pieces.push(Piece::Value(value.clone()));
+        pieces.push(text(")"));
+    }
+
+    pieces.push(text("] }"));
+    pieces
+}
+
+/*
+ * The REPL's layout for `format_with_base`. `open` holds the hash tables
+ * currently being written.
+ */
+struct BaseLayout {
+    base: u32,
+    open: Vec<*const ()>,
+}
+
+impl Layout for BaseLayout {
+    fn expand(&mut self, expr: &LispExpr) -> Vec<Piece> {
+        match expr {
+            LispExpr::Number(number) => vec![Piece::Text(format!(
+                "Number({})",
+                format_number(*number, self.base)
+            ))],
+            LispExpr::Promise(promise) => match *promise.borrow() {
+                PromiseState::Delayed(_) => vec![text("Promise(Delayed)")],
+                PromiseState::Forced(_) => vec![text("Promise(Forced)")],
+            },
+            LispExpr::Hash(hash) => {
+                let pointer = Rc::as_ptr(hash) as *const ();
+
+                if self.open.contains(&pointer) {
+                    return vec![text("Hash(..)")];
+                }
+
+                let mut entries: Vec<_> = hash
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+                self.open.push(pointer);
+                let mut pieces = vec![text("Hash({")];
+
+                for (index, (key, value)) in entries.into_iter().enumerate() {
+                    if index > 0 {
+                        pieces.push(text(", "));
+                    }
+
+                    pieces.push(Piece::Value(key.to_expr()));
+                    pieces.push(text(": "));
+                    pieces.push(Piece::Value(value));
+                }
+
+                pieces.push(text("})"));
+                pieces.push(Piece::Leave);
+                pieces
+            }
+            LispExpr::Record { type_name, fields } => record_pieces(type_name, fields),
+            _ => DebugLayout.expand(expr),
+        }
+    }
+
+    fn leave(&mut self) {
+        self.open.pop();
+    }
+}
+
+fn format_number(number: i64, base: u32) -> String {
+    let sign = if number < 0 { "-" } else { "" };
+    let magnitude = number.unsigned_abs();
+
+    match base {
+        2 => format!("{}0b{:b}", sign, magnitude),
+        8 => format!("{}0o{:o}", sign, magnitude),
+        16 => format!("{}0x{:x}", sign, magnitude),
+        _ => number.to_string(),
+    }
+}
+
+/*
+ * A promise created by `delay`, holding either the unevaluated expression or
+ * the memoized result of the first `force`.
+ */
+pub enum PromiseState {
+    Delayed(LispExpr),
+    Forced(LispExpr),
+}
+
+// A forced value isn't shown, for the same reason promises compare by identity.
+impl fmt::Debug for PromiseState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PromiseState::Delayed(expr) => f.debug_tuple("Delayed").field(expr).finish(),
+            PromiseState::Forced(_) => write!(f, "Forced(..)"),
+        }
+    }
+}
+
+type PromiseCell = Rc<RefCell<PromiseState>>;
+
+/*
+ * The subset of values that can be used as hash table keys.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashKey {
+    Number(i64),
+    Symbol(String),
+}
+
+impl HashKey {
+    fn from_expr(expr: &LispExpr) -> Result<HashKey, String> {
+        match expr {
+            LispExpr::Number(number) => Ok(HashKey::Number(*number)),
+            LispExpr::Symbol(symbol) => Ok(HashKey::Symbol(symbol.clone())),
+            _ => Err("Hash keys must be numbers or symbols".into()),
+        }
+    }
+
+    fn to_expr(&self) -> LispExpr {
+        match self {
+            HashKey::Number(number) => LispExpr::Number(*number),
+            HashKey::Symbol(symbol) => LispExpr::Symbol(symbol.clone()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -26,7 +434,10 @@ enum TokenizerState {
     Start,
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
     Number,
+    InvalidNumber,
     Symbol,
     Whitespace,
 }
@@ -35,279 +446,4622 @@ enum TokenizerState {
 pub enum TokenType {
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
     Number(i64),
     Symbol(String),
 }
 
+/*
+ * A range of byte offsets into the source text, end exclusive.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
 #[derive(Debug)]
 pub struct Token {
     pub token_type: TokenType,
+    pub span: Span,
 }
 
-pub fn tokenize(expr: &str) -> Vec<Token> {
-    let mut tokens = Vec::new();
-    let mut start_index = 0;
+/*
+ * An error produced while tokenizing or parsing, pointing at the offending
+ * source text when there is one.
+ */
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Option<Span>,
+}
 
-    loop {
-        let mut state = TokenizerState::Start;
-        let mut end_index = start_index;
+impl ParseError {
+    fn new(message: String, span: Span) -> ParseError {
+        ParseError {
+            message,
+            span: Some(span),
+        }
+    }
+}
 
-        for character in expr[start_index..].chars() {
-            let next = match state {
-                TokenizerState::Start => match character {
-                    '(' => Some(TokenizerState::LeftParen),
-                    ')' => Some(TokenizerState::RightParen),
-                    '0'..='9' => Some(TokenizerState::Number),
-                    'a'..='z' | 'A'..='Z' | '+' | '-' | '*' | '/' => Some(TokenizerState::Symbol),
-                    character if character.is_whitespace() => Some(TokenizerState::Whitespace),
-                    _ => None,
-                },
-                TokenizerState::LeftParen | TokenizerState::RightParen => None,
-                TokenizerState::Number => match character {
-                    '0'..='9' => Some(TokenizerState::Number),
-                    _ => None,
-                },
-                TokenizerState::Symbol => match character {
-                    'a'..='z' | 'A'..='Z' | '0'..='9' | '+' | '-' | '*' | '/' => {
-                        Some(TokenizerState::Symbol)
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "{} at {}", self.message, span),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+fn is_symbol_start(character: char) -> bool {
+    matches!(
+        character,
+        'a'..='z' | 'A'..='Z' | '+' | '-' | '*' | '/' | '!' | '?' | '=' | '.' | '<' | '>'
+    )
+}
+
+fn is_symbol_character(character: char) -> bool {
+    is_symbol_start(character) || character.is_ascii_digit()
+}
+
+/*
+ * A token starting with a digit is a number and may only contain digits, so a
+ * run like `1a` is rejected as an invalid number literal rather than being
+ * split into `1` and `a`. A token starting with a letter or operator is a
+ * symbol and may contain digits, so `a1` and `+1` are single symbols.
+ */
+pub struct Tokenizer<'a> {
+    expr: &'a str,
+    options: TokenizerOptions,
+    start_index: usize,
+    failed: bool,
+}
+
+/*
+ * Lexer settings for stricter or looser dialects. The defaults are what
+ * `tokenize` uses.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct TokenizerOptions {
+    // Accept number literals like `007`, read as 7.
+    pub allow_leading_zeros: bool,
+    // Accept digit separators in number literals, like `1_000`.
+    pub allow_underscores: bool,
+}
+
+impl Default for TokenizerOptions {
+    fn default() -> TokenizerOptions {
+        TokenizerOptions {
+            allow_leading_zeros: true,
+            allow_underscores: false,
+        }
+    }
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(expr: &'a str) -> Tokenizer<'a> {
+        Tokenizer::with_options(expr, TokenizerOptions::default())
+    }
+
+    pub fn with_options(expr: &'a str, options: TokenizerOptions) -> Tokenizer<'a> {
+        Tokenizer {
+            expr,
+            options,
+            start_index: 0,
+            failed: false,
+        }
+    }
+
+    fn read_token(&mut self) -> Option<Result<Token, ParseError>> {
+        let expr = self.expr;
+
+        loop {
+            let start_index = self.start_index;
+            let mut state = TokenizerState::Start;
+            let mut end_index = start_index;
+
+            for character in expr[start_index..].chars() {
+                let next = match state {
+                    TokenizerState::Start => match character {
+                        '(' => Some(TokenizerState::LeftParen),
+                        ')' => Some(TokenizerState::RightParen),
+                        '[' => Some(TokenizerState::LeftBracket),
+                        ']' => Some(TokenizerState::RightBracket),
+                        '0'..='9' => Some(TokenizerState::Number),
+                        character if is_symbol_start(character) => Some(TokenizerState::Symbol),
+                        character if character.is_whitespace() => Some(TokenizerState::Whitespace),
+                        _ => None,
+                    },
+                    TokenizerState::LeftParen
+                    | TokenizerState::RightParen
+                    | TokenizerState::LeftBracket
+                    | TokenizerState::RightBracket => None,
+                    TokenizerState::Number => match character {
+                        '0'..='9' => Some(TokenizerState::Number),
+                        '_' if self.options.allow_underscores => Some(TokenizerState::Number),
+                        character if is_symbol_start(character) => {
+                            Some(TokenizerState::InvalidNumber)
+                        }
+                        _ => None,
+                    },
+                    TokenizerState::InvalidNumber => match character {
+                        character if is_symbol_character(character) => {
+                            Some(TokenizerState::InvalidNumber)
+                        }
+                        _ => None,
+                    },
+                    TokenizerState::Symbol => match character {
+                        character if is_symbol_character(character) => Some(TokenizerState::Symbol),
+                        _ => None,
+                    },
+                    TokenizerState::Whitespace => {
+                        if character.is_whitespace() {
+                            Some(TokenizerState::Whitespace)
+                        } else {
+                            None
+                        }
+                    }
+                };
+
+                if let Some(next_state) = next {
+                    state = next_state;
+                    end_index += character.len_utf8();
+                } else {
+                    break;
+                }
+            }
+
+            let token_string = &expr[start_index..end_index];
+            let span = Span {
+                start: start_index,
+                end: end_index,
+            };
+            self.start_index = end_index;
+
+            let token_type = match state {
+                TokenizerState::Start => match expr[start_index..].chars().next() {
+                    Some(character) => {
+                        let span = Span {
+                            start: start_index,
+                            end: start_index + character.len_utf8(),
+                        };
+
+                        return Some(Err(ParseError::new(
+                            format!("Unexpected character {:?}", character),
+                            span,
+                        )));
                     }
-                    _ => None,
+                    None => return None,
                 },
-                TokenizerState::Whitespace => {
-                    if character.is_whitespace() {
-                        Some(TokenizerState::Whitespace)
-                    } else {
-                        None
+                TokenizerState::LeftParen => TokenType::LeftParen,
+                TokenizerState::RightParen => TokenType::RightParen,
+                TokenizerState::LeftBracket => TokenType::LeftBracket,
+                TokenizerState::RightBracket => TokenType::RightBracket,
+                TokenizerState::Number
+                    if !self.options.allow_leading_zeros
+                        && token_string.len() > 1
+                        && token_string.starts_with('0') =>
+                {
+                    return Some(Err(ParseError::new(
+                        format!("Leading zeros are not allowed: {}", token_string),
+                        span,
+                    )));
+                }
+                TokenizerState::Number => match token_string.replace('_', "").parse() {
+                    Ok(number) => TokenType::Number(number),
+                    Err(_) => {
+                        return Some(Err(ParseError::new(
+                            format!("Number literal out of range: {}", token_string),
+                            span,
+                        )));
                     }
+                },
+                TokenizerState::InvalidNumber => {
+                    return Some(Err(ParseError::new(
+                        format!("Invalid number literal: {}", token_string),
+                        span,
+                    )));
                 }
+                TokenizerState::Symbol => TokenType::Symbol(token_string.into()),
+                TokenizerState::Whitespace => continue,
             };
 
-            if let Some(next_state) = next {
-                state = next_state;
-                end_index += character.len_utf8();
-            } else {
-                break;
-            }
+            return Some(Ok(Token { token_type, span }));
         }
+    }
+}
 
-        let token_string = &expr[start_index..end_index];
-        start_index = end_index;
+/*
+ * Yields tokens lazily so consumers can stop early. After yielding an error the
+ * tokenizer is exhausted.
+ */
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<Token, ParseError>;
 
-        let token_type = match state {
-            TokenizerState::Start => break,
-            TokenizerState::LeftParen => TokenType::LeftParen,
-            TokenizerState::RightParen => TokenType::RightParen,
-            TokenizerState::Number => TokenType::Number(token_string.parse().unwrap()),
-            TokenizerState::Symbol => TokenType::Symbol(token_string.into()),
-            TokenizerState::Whitespace => continue,
-        };
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
 
-        tokens.push(Token {
-            token_type: token_type,
-        })
+        let item = self.read_token();
+        self.failed = matches!(item, Some(Err(_)));
+
+        item
     }
+}
+
+pub fn tokenize(expr: &str) -> Result<Vec<Token>, ParseError> {
+    Tokenizer::new(expr).collect()
+}
+
+pub fn tokenize_with(expr: &str, options: TokenizerOptions) -> Result<Vec<Token>, ParseError> {
+    Tokenizer::with_options(expr, options).collect()
+}
 
-    return tokens;
+/*
+ * Square brackets delimit lists just like parens, so `(let ([x 1]) x)` reads
+ * the same as `(let ((x 1)) x)`. By default a list may close with either kind;
+ * `require_matching_brackets` makes `[` close only with `]` and `(` with `)`.
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParserOptions {
+    pub require_matching_brackets: bool,
 }
 
 pub struct Parser {
     token_stream: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    options: ParserOptions,
+    // How many lists are currently open.
+    depth: usize,
+}
+
+// How deeply lists may nest before parsing fails, rather than overflowing the
+// stack on input like `((((...))))`.
+const MAX_PARSE_DEPTH: usize = 1000;
+
+fn is_closing(token_type: &TokenType) -> bool {
+    matches!(token_type, TokenType::RightParen | TokenType::RightBracket)
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
+        Parser::with_options(tokens, ParserOptions::default())
+    }
+
+    pub fn with_options(tokens: Vec<Token>, options: ParserOptions) -> Parser {
         Parser {
             token_stream: tokens.into_iter().peekable(),
+            options,
+            depth: 0,
         }
     }
 
-    pub fn parse(&mut self) -> Result<LispExpr, String> {
+    pub fn parse(&mut self) -> Result<LispExpr, ParseError> {
         if let Some(token) = self.token_stream.next() {
             match token.token_type {
-                TokenType::LeftParen => self.parse_form(),
-                TokenType::RightParen => {
-                    return Err("Unexpected right paren found.".into());
-                }
+                TokenType::LeftParen => self.parse_form(TokenType::RightParen, token.span),
+                TokenType::LeftBracket => self.parse_form(TokenType::RightBracket, token.span),
+                TokenType::RightParen => Err(ParseError::new(
+                    "Unexpected right paren found".into(),
+                    token.span,
+                )),
+                TokenType::RightBracket => Err(ParseError::new(
+                    "Unexpected right bracket found".into(),
+                    token.span,
+                )),
                 TokenType::Number(number) => Ok(LispExpr::Number(number)),
-                TokenType::Symbol(ref string) => {
-                    let symbol = string.clone();
+                TokenType::Symbol(ref string) => match &string[..] {
+                    "true" => Ok(LispExpr::Bool(true)),
+                    "false" => Ok(LispExpr::Bool(false)),
+                    _ => {
+                        let symbol = string.clone();
 
-                    Ok(LispExpr::Symbol(symbol))
-                }
+                        Ok(LispExpr::Symbol(symbol))
+                    }
+                },
             }
         } else {
-            return Err("Invalid expression".into());
+            Err(ParseError {
+                message: "Invalid expression".into(),
+                span: None,
+            })
         }
     }
 
-    fn parse_form(&mut self) -> Result<LispExpr, String> {
-        if let Some(_) = self.token_stream.peek() {
-            let mut list = Vec::new();
-
-            while let Some(token) = self.token_stream.peek() {
-                if token.token_type == TokenType::RightParen {
-                    break;
-                }
+    /*
+     * Parses every remaining top-level form, stopping at the first error.
+     */
+    pub fn parse_all(&mut self) -> Result<Vec<LispExpr>, ParseError> {
+        let mut forms = Vec::new();
 
-                match self.parse() {
-                    Ok(value) => list.push(value),
-                    error => return error,
-                }
-            }
+        while !self.is_empty() {
+            forms.push(self.parse()?);
+        }
 
-            // Consume the closing right paren from the token stream.
-            self.token_stream.next();
+        Ok(forms)
+    }
 
-            Ok(LispExpr::List(list))
-        } else {
-            Err("Invalid expression".into())
-        }
+    /*
+     * Returns true once every token has been consumed, which is how callers
+     * parsing a sequence of top-level forms know to stop.
+     */
+    pub fn is_empty(&mut self) -> bool {
+        self.token_stream.peek().is_none()
     }
-}
 
-pub struct Interpreter {}
+    fn parse_form(&mut self, close: TokenType, open_span: Span) -> Result<LispExpr, ParseError> {
+        if self.depth >= MAX_PARSE_DEPTH {
+            // Skip the rest of the top-level form so that parsing can resume
+            // after it, as `check_file` does.
+            self.skip_lists(self.depth + 1);
 
-impl Interpreter {
-    pub fn new() -> Interpreter {
-        Interpreter {}
-    }
-
-    pub fn evaluate(&self, ast: LispExpr) -> Result<LispExpr, String> {
-        match ast {
-            LispExpr::List(values) => match values[0] {
-                LispExpr::Symbol(ref symbol) => match &symbol[..] {
-                    "+" => {
-                        let result = values[1..]
-                            .iter()
-                            .map(|ast| self.evaluate(ast.clone()))
-                            .try_fold(0, |acc, value| match value {
-                                Ok(LispExpr::Number(number)) => Ok(acc + number),
-                                _ => Err("Invalid + operation".into()),
-                            });
-
-                        if let Ok(sum) = result {
-                            Ok(LispExpr::Number(sum))
-                        } else {
-                            Err(result.unwrap_err())
-                        }
-                    }
-                    "-" => {
-                        if values.len() == 2 {
-                            if let Ok(LispExpr::Number(initial_value)) =
-                                self.evaluate(values[1].clone())
-                            {
-                                return Ok(LispExpr::Number(-initial_value));
-                            }
-                        }
+            return Err(ParseError::new(
+                "Maximum nesting depth exceeded".into(),
+                open_span,
+            ));
+        }
 
-                        if let Ok(LispExpr::Number(initial_value)) =
-                            self.evaluate(values[1].clone())
-                        {
-                            let result = values[2..]
-                                .iter()
-                                .map(|ast| self.evaluate(ast.clone()))
-                                .try_fold(initial_value, |acc, value| match value {
-                                    Ok(LispExpr::Number(number)) => Ok(acc - number),
-                                    _ => Err("Invalid - operation".into()),
-                                });
-
-                            if let Ok(sum) = result {
-                                Ok(LispExpr::Number(sum))
-                            } else {
-                                Err(result.unwrap_err())
-                            }
-                        } else {
-                            Err("Invalid - operation".into())
-                        }
-                    }
-                    "/" => {
-                        if values.len() < 3 {
-                            return Err("Invalid / operation".into());
-                        }
+        self.depth += 1;
+        let result = self.parse_list(close, open_span);
+        self.depth -= 1;
 
-                        if let Ok(LispExpr::Number(initial_value)) =
-                            self.evaluate(values[1].clone())
-                        {
-                            let result = values[2..]
-                                .iter()
-                                .map(|ast| self.evaluate(ast.clone()))
-                                .try_fold(initial_value, |acc, value| match value {
-                                    Ok(LispExpr::Number(number)) => Ok(acc / number),
-                                    _ => Err("Invalid / operation".into()),
-                                });
-
-                            if let Ok(sum) = result {
-                                Ok(LispExpr::Number(sum))
-                            } else {
-                                Err(result.unwrap_err())
-                            }
-                        } else {
-                            Err("Invalid / operation".into())
-                        }
-                    }
-                    "*" => {
-                        if values.len() < 3 {
-                            return Err("Invalid * operation".into());
-                        }
+        result
+    }
 
-                        if let Ok(LispExpr::Number(initial_value)) =
-                            self.evaluate(values[1].clone())
-                        {
-                            let result = values[2..]
-                                .iter()
-                                .map(|ast| self.evaluate(ast.clone()))
-                                .try_fold(initial_value, |acc, value| match value {
-                                    Ok(LispExpr::Number(number)) => Ok(acc * number),
-                                    _ => Err("Invalid * operation".into()),
-                                });
-
-                            if let Ok(sum) = result {
-                                Ok(LispExpr::Number(sum))
-                            } else {
-                                Err(result.unwrap_err())
-                            }
-                        } else {
-                            Err("Invalid * operation".into())
+    // Consumes tokens until `open` lists have been closed.
+    fn skip_lists(&mut self, mut open: usize) {
+        while open > 0 {
+            match self.token_stream.next().map(|token| token.token_type) {
+                Some(TokenType::LeftParen) | Some(TokenType::LeftBracket) => open += 1,
+                Some(TokenType::RightParen) | Some(TokenType::RightBracket) => open -= 1,
+                Some(_) => {}
+                None => break,
+            }
+        }
+    }
+
+    fn parse_list(&mut self, close: TokenType, open_span: Span) -> Result<LispExpr, ParseError> {
+        let mut list = Vec::new();
+
+        while let Some(token) = self.token_stream.peek() {
+            if is_closing(&token.token_type) {
+                break;
+            }
+
+            list.push(self.parse()?);
+        }
+
+        // Consume the closing token from the token stream, which is only
+        // missing when the input ended inside the list.
+        match self.token_stream.next() {
+            None if close == TokenType::RightBracket => Err(ParseError::new(
+                "Missing closing right bracket".into(),
+                open_span,
+            )),
+            None => Err(ParseError::new(
+                "Missing closing right paren".into(),
+                open_span,
+            )),
+            Some(token) if self.options.require_matching_brackets && token.token_type != close => {
+                let expected = if close == TokenType::RightBracket {
+                    ']'
+                } else {
+                    ')'
+                };
+
+                Err(ParseError::new(
+                    format!("Mismatched closing delimiter, expected '{}'", expected),
+                    token.span,
+                ))
+            }
+            Some(_) => Ok(LispExpr::list(list)),
+        }
+    }
+}
+
+/*
+ * Checks that a form received between `min` and `max` (inclusive) arguments,
+ * producing a uniform error message naming the form otherwise.
+ */
+fn check_arity(
+    name: &str,
+    args: &[LispExpr],
+    min: usize,
+    max: Option<usize>,
+) -> Result<(), String> {
+    let count = args.len();
+    let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+
+    match max {
+        Some(max) if min == max && count != min => Err(format!(
+            "{} expects exactly {} {}, got {}",
+            name,
+            min,
+            plural(min),
+            count
+        )),
+        Some(max) if count > max => Err(format!(
+            "{} expects at most {} {}, got {}",
+            name,
+            max,
+            plural(max),
+            count
+        )),
+        _ if count < min => Err(format!(
+            "{} expects at least {} {}, got {}",
+            name,
+            min,
+            plural(min),
+            count
+        )),
+        _ => Ok(()),
+    }
+}
+
+/*
+ * A builtin function receives its arguments already evaluated. Builtins are
+ * stored behind an `Rc` so one can be looked up and then called with mutable
+ * access to the interpreter that owns it.
+ */
+pub type Builtin = Rc<dyn Fn(&mut Interpreter, &[LispExpr]) -> Result<LispExpr, String>>;
+
+/*
+ * Type checks for builtin arguments, with one error message format shared by
+ * every builtin: "take expects a list, got Number(1)".
+ */
+fn as_number(name: &str, expr: &LispExpr) -> Result<i64, String> {
+    match expr {
+        LispExpr::Number(number) => Ok(*number),
+        _ => Err(format!("{} expects a number, got {:?}", name, expr)),
+    }
+}
+
+fn as_list<'a>(name: &str, expr: &'a LispExpr) -> Result<&'a [LispExpr], String> {
+    match expr {
+        LispExpr::List(values) => Ok(values),
+        _ => Err(format!("{} expects a list, got {:?}", name, expr)),
+    }
+}
+
+fn as_numbers(name: &str, args: &[LispExpr]) -> Result<Vec<i64>, String> {
+    args.iter().map(|arg| as_number(name, arg)).collect()
+}
+
+/*
+ * Folds the numbers with a checked operation, failing with "Integer overflow in
+ * +" (or the like) instead of wrapping or panicking.
+ */
+fn checked_fold(
+    name: &str,
+    initial_value: i64,
+    numbers: &[i64],
+    operation: fn(i64, i64) -> Option<i64>,
+) -> Result<LispExpr, String> {
+    numbers
+        .iter()
+        .try_fold(initial_value, |acc, number| operation(acc, *number))
+        .map(LispExpr::Number)
+        .ok_or_else(|| format!("Integer overflow in {}", name))
+}
+
+fn builtin_add(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    checked_fold("+", 0, &as_numbers("+", args)?, i64::checked_add)
+}
+
+fn builtin_subtract(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    // A single argument is negated, while two or more subtract every trailing
+    // argument from the first.
+    match &as_numbers("-", args)?[..] {
+        [initial_value] => initial_value
+            .checked_neg()
+            .map(LispExpr::Number)
+            .ok_or_else(|| "Integer overflow in unary -".into()),
+        [initial_value, rest @ ..] => checked_fold("-", *initial_value, rest, i64::checked_sub),
+        [] => unreachable!("check_arity requires an argument"),
+    }
+}
+
+fn builtin_divide(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let numbers = as_numbers("/", args)?;
+
+    if numbers[1..].contains(&0) {
+        return Err("Division by zero in /".into());
+    }
+
+    // With zero divisors ruled out, only `i64::MIN` divided by -1 overflows.
+    checked_fold("/", numbers[0], &numbers[1..], i64::checked_div)
+}
+
+fn builtin_multiply(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    checked_fold("*", 1, &as_numbers("*", args)?, i64::checked_mul)
+}
+
+/*
+ * Integer division rounding the quotient in the given direction, returning
+ * `(quotient remainder)` with `dividend = quotient * divisor + remainder`.
+ */
+fn rounded_division(
+    name: &str,
+    args: &[LispExpr],
+    rounding: fn(i128, i128, i128) -> i128,
+) -> Result<LispExpr, String> {
+    let numbers = as_numbers(name, args)?;
+    let (dividend, divisor) = (i128::from(numbers[0]), i128::from(numbers[1]));
+
+    if divisor == 0 {
+        return Err(format!("Division by zero in {}", name));
+    }
+
+    // Work in i128 so the adjustments can't overflow; only the quotient of
+    // `i64::MIN` by -1 ends up out of range.
+    let quotient = rounding(dividend / divisor, dividend % divisor, divisor);
+    let remainder = dividend - quotient * divisor;
+
+    match (i64::try_from(quotient), i64::try_from(remainder)) {
+        (Ok(quotient), Ok(remainder)) => Ok(LispExpr::list(vec![
+            LispExpr::Number(quotient),
+            LispExpr::Number(remainder),
+        ])),
+        _ => Err(format!("Integer overflow in {}", name)),
+    }
+}
+
+// Each rounding takes the truncated quotient, its remainder and the divisor.
+fn builtin_floor_divide(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    rounded_division("floor/", args, |quotient, remainder, divisor| {
+        if remainder != 0 && (remainder < 0) != (divisor < 0) {
+            quotient - 1
+        } else {
+            quotient
+        }
+    })
+}
+
+fn builtin_ceiling_divide(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    rounded_division("ceiling/", args, |quotient, remainder, divisor| {
+        if remainder != 0 && (remainder < 0) == (divisor < 0) {
+            quotient + 1
+        } else {
+            quotient
+        }
+    })
+}
+
+// Rounds to the nearest quotient, with ties going to the even one.
+fn builtin_round_divide(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    rounded_division("round/", args, |quotient, remainder, divisor| {
+        let twice = 2 * remainder.abs();
+
+        if twice > divisor.abs() || (twice == divisor.abs() && quotient % 2 != 0) {
+            if (remainder < 0) == (divisor < 0) {
+                quotient + 1
+            } else {
+                quotient - 1
+            }
+        } else {
+            quotient
+        }
+    })
+}
+
+fn builtin_truncate_divide(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    rounded_division("truncate/", args, |quotient, _, _| quotient)
+}
+
+fn checked_power(name: &str, args: &[LispExpr], exponent: u32) -> Result<LispExpr, String> {
+    as_number(name, &args[0])?
+        .checked_pow(exponent)
+        .map(LispExpr::Number)
+        .ok_or_else(|| format!("Integer overflow in {}", name))
+}
+
+fn builtin_square(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    checked_power("square", args, 2)
+}
+
+fn builtin_cube(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    checked_power("cube", args, 3)
+}
+
+fn builtin_force(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    match args[0] {
+        LispExpr::Promise(ref promise) => interpreter.force(promise),
+        ref value => Ok(value.clone()),
+    }
+}
+
+fn builtin_identity(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    Ok(args[0].clone())
+}
+
+fn check_number_predicate(
+    name: &str,
+    args: &[LispExpr],
+    predicate: fn(i64) -> bool,
+) -> Result<LispExpr, String> {
+    as_number(name, &args[0]).map(|number| LispExpr::Bool(predicate(number)))
+}
+
+fn builtin_is_zero(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    check_number_predicate("zero?", args, |number| number == 0)
+}
+
+fn builtin_is_positive(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    check_number_predicate("positive?", args, |number| number > 0)
+}
+
+fn builtin_is_negative(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    check_number_predicate("negative?", args, |number| number < 0)
+}
+
+fn builtin_is_even(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    check_number_predicate("even?", args, |number| number % 2 == 0)
+}
+
+fn builtin_is_odd(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    check_number_predicate("odd?", args, |number| number % 2 != 0)
+}
+
+/*
+ * Floor of the square root of a non-negative integer, found with Newton's
+ * method on integers so large inputs don't lose precision through floats.
+ */
+fn integer_sqrt(number: i64) -> i64 {
+    if number < 2 {
+        return number;
+    }
+
+    // Starting above the root, each step strictly decreases until it reaches
+    // the floor. `number / 2 + 1` can't overflow, unlike `number + 1`.
+    let mut root = number / 2 + 1;
+    let mut next = (root + number / root) / 2;
+
+    while next < root {
+        root = next;
+        next = (root + number / root) / 2;
+    }
+
+    root
+}
+
+fn non_negative_argument(name: &str, args: &[LispExpr]) -> Result<i64, String> {
+    match as_number(name, &args[0])? {
+        number if number < 0 => Err(format!(
+            "{} expects a non-negative number, got {}",
+            name, number
+        )),
+        number => Ok(number),
+    }
+}
+
+fn builtin_isqrt(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let number = non_negative_argument("isqrt", args)?;
+
+    Ok(LispExpr::Number(integer_sqrt(number)))
+}
+
+// There are no multiple values, so the root and remainder come back as a list.
+fn builtin_exact_integer_sqrt(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let number = non_negative_argument("exact-integer-sqrt", args)?;
+    let root = integer_sqrt(number);
+
+    Ok(LispExpr::list(vec![
+        LispExpr::Number(root),
+        LispExpr::Number(number - root * root),
+    ]))
+}
+
+/*
+ * Extracts the `(list count)` arguments shared by `take` and `drop`. Counts
+ * past the end of the list are allowed and clamp to its length.
+ */
+fn list_and_count<'a>(name: &str, args: &'a [LispExpr]) -> Result<(&'a [LispExpr], usize), String> {
+    match (&args[0], &args[1]) {
+        (LispExpr::List(_), LispExpr::Number(count)) if *count < 0 => Err(format!(
+            "{} expects a non-negative count, got {}",
+            name, count
+        )),
+        (LispExpr::List(values), LispExpr::Number(count)) => {
+            Ok((values, (*count as usize).min(values.len())))
+        }
+        _ => Err(format!("{} expects a list and a count", name)),
+    }
+}
+
+fn builtin_take(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let (values, count) = list_and_count("take", args)?;
+
+    Ok(LispExpr::list(values[..count].to_vec()))
+}
+
+fn builtin_drop(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let (values, count) = list_and_count("drop", args)?;
+
+    Ok(LispExpr::list(values[count..].to_vec()))
+}
+
+// Arity and type errors name the caller, but both report an empty list as
+// "last of empty list".
+fn last_element<'a>(name: &str, args: &'a [LispExpr]) -> Result<&'a LispExpr, String> {
+    as_list(name, &args[0])?
+        .last()
+        .ok_or_else(|| "last of empty list".to_string())
+}
+
+fn builtin_last(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    last_element("last", args).cloned()
+}
+
+// Lists aren't built from cons cells, so the last pair is a one-element list.
+fn builtin_last_pair(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let last = last_element("last-pair", args)?;
+
+    Ok(LispExpr::list(vec![last.clone()]))
+}
+
+/*
+ * Walks the nested lists with a stack of iterators rather than recursion, so a
+ * list nested thousands deep can't overflow the stack.
+ */
+fn flatten_into(expr: &LispExpr, output: &mut Vec<LispExpr>) {
+    let mut pending = vec![std::slice::from_ref(expr).iter()];
+
+    while let Some(values) = pending.last_mut() {
+        match values.next() {
+            Some(LispExpr::List(inner)) => pending.push(inner.iter()),
+            Some(value) => output.push(value.clone()),
+            None => {
+                pending.pop();
+            }
+        }
+    }
+}
+
+fn builtin_flatten(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    match args[0] {
+        LispExpr::List(_) => {
+            let mut values = Vec::new();
+            flatten_into(&args[0], &mut values);
+
+            Ok(LispExpr::list(values))
+        }
+        _ => Err("flatten expects a list".into()),
+    }
+}
+
+/*
+ * Returns a shallow copy: the new list has its own storage, so it is not
+ * `eqv?` to the original, but its elements, nested lists included, are shared.
+ */
+fn builtin_list_copy(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    Ok(LispExpr::list(as_list("list-copy", &args[0])?.to_vec()))
+}
+
+// Assertions return the empty list (nil) when they pass.
+fn builtin_assert(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    if args[0].is_truthy() {
+        Ok(LispExpr::nil())
+    } else {
+        Err("Assertion failed".into())
+    }
+}
+
+fn builtin_assert_equal(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    if args[0] == args[1] {
+        Ok(LispExpr::nil())
+    } else {
+        Err(format!(
+            "Assertion failed: expected {:?}, got {:?}",
+            args[1], args[0]
+        ))
+    }
+}
+
+/*
+ * Shared by `max-by` and `min-by`: returns the element whose key, computed by
+ * applying the procedure to it, is the largest (or smallest). Ties keep the
+ * earliest element.
+ */
+fn select_by(
+    interpreter: &mut Interpreter,
+    name: &str,
+    args: &[LispExpr],
+    ordering: Ordering,
+) -> Result<LispExpr, String> {
+    let values = match args[1] {
+        LispExpr::List(ref values) if values.is_empty() => {
+            return Err(format!("{} of empty list", name));
+        }
+        LispExpr::List(ref values) => values,
+        _ => return Err(format!("{} expects a procedure and a list", name)),
+    };
+
+    let mut best: Option<(&LispExpr, i64)> = None;
+
+    for value in values.iter() {
+        let key = match interpreter.apply(&args[0], std::slice::from_ref(value))? {
+            LispExpr::Number(key) => key,
+            key => return Err(format!("{} key must be a number, got {:?}", name, key)),
+        };
+
+        match best {
+            Some((_, best_key)) if key.cmp(&best_key) != ordering => {}
+            _ => best = Some((value, key)),
+        }
+    }
+
+    Ok(best.map(|(value, _)| value.clone()).unwrap())
+}
+
+fn builtin_max_by(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    select_by(interpreter, "max-by", args, Ordering::Greater)
+}
+
+fn builtin_min_by(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    select_by(interpreter, "min-by", args, Ordering::Less)
+}
+
+// Lists are stored as vectors and always end in nil, so every list is a
+// proper list. Any non-empty list is a pair; the empty list is not.
+fn builtin_is_list(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    Ok(LispExpr::Bool(matches!(args[0], LispExpr::List(_))))
+}
+
+fn builtin_is_pair(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    Ok(LispExpr::Bool(
+        matches!(args[0], LispExpr::List(ref values) if !values.is_empty()),
+    ))
+}
+
+fn builtin_pretty_print(
+    interpreter: &mut Interpreter,
+    args: &[LispExpr],
+) -> Result<LispExpr, String> {
+    writeln!(interpreter.output, "{}", args[0].pretty(0))
+        .map_err(|error| format!("Could not write output: {}", error))?;
+
+    Ok(LispExpr::nil())
+}
+
+/*
+ * Writes the arguments as lisp source separated by spaces, followed by a
+ * newline when `newline` is set. Shared by `print` and `println`.
+ */
+fn write_values(
+    interpreter: &mut Interpreter,
+    args: &[LispExpr],
+    newline: bool,
+) -> Result<LispExpr, String> {
+    let line: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    let ending = if newline { "\n" } else { "" };
+
+    write!(interpreter.output, "{}{}", line.join(" "), ending)
+        .and_then(|_| interpreter.output.flush())
+        .map_err(|error| format!("Could not write output: {}", error))?;
+
+    Ok(LispExpr::nil())
+}
+
+fn builtin_print(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    write_values(interpreter, args, false)
+}
+
+fn builtin_println(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    write_values(interpreter, args, true)
+}
+
+fn builtin_define_param(
+    interpreter: &mut Interpreter,
+    args: &[LispExpr],
+) -> Result<LispExpr, String> {
+    match args[0] {
+        LispExpr::Symbol(ref name) => {
+            interpreter.define_param(name, args[1].clone());
+
+            Ok(LispExpr::nil())
+        }
+        _ => Err("define-param expects a symbol and a value".into()),
+    }
+}
+
+fn builtin_get_param(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    match args[0] {
+        LispExpr::Symbol(ref name) => interpreter
+            .params
+            .get(name)
+            .and_then(|stack| stack.last())
+            .cloned()
+            .ok_or_else(|| format!("Undefined parameter: {}", name)),
+        _ => Err("get-param expects a symbol".into()),
+    }
+}
+
+/*
+ * Groups the i-th elements of each list together, stopping at the end of the
+ * shortest list.
+ */
+fn transpose(name: &str, lists: &[LispExpr]) -> Result<Vec<Vec<LispExpr>>, String> {
+    let lists = lists
+        .iter()
+        .map(|list| match list {
+            LispExpr::List(values) => Ok(values),
+            _ => Err(format!("{} expects lists, got {:?}", name, list)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let length = lists.iter().map(|values| values.len()).min().unwrap_or(0);
+
+    Ok((0..length)
+        .map(|index| lists.iter().map(|values| values[index].clone()).collect())
+        .collect())
+}
+
+fn transpose_to_list(name: &str, lists: &[LispExpr]) -> Result<LispExpr, String> {
+    let tuples = transpose(name, lists)?;
+
+    Ok(LispExpr::list(
+        tuples.into_iter().map(LispExpr::list).collect(),
+    ))
+}
+
+fn builtin_zip(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    transpose_to_list("zip", args)
+}
+
+// Unzipping is zipping the tuples themselves: `(unzip ((1 a) (2 b)))` is the
+// same as `(zip (1 a) (2 b))`.
+fn builtin_unzip(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    match args[0] {
+        LispExpr::List(ref tuples) => transpose_to_list("unzip", tuples),
+        _ => Err("unzip expects a list of lists".into()),
+    }
+}
+
+// With several lists the procedure gets one element from each, stopping at the
+// end of the shortest: `(map + (1 2 3) (10 20))` is `(11 22)`.
+fn builtin_map(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    transpose("map", &args[1..])?
+        .iter()
+        .map(|values| interpreter.apply(&args[0], values))
+        .collect::<Result<Vec<_>, _>>()
+        .map(LispExpr::list)
+}
+
+// Like `map`, but only for the procedure's side effects: results are discarded
+// and nil is returned.
+fn builtin_for_each(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    for values in transpose("for-each", &args[1..])? {
+        interpreter.apply(&args[0], &values)?;
+    }
+
+    Ok(LispExpr::nil())
+}
+
+/*
+ * A stream is the list `(head promise)`, where forcing the promise gives the
+ * rest of the stream, and the empty list is the empty stream.
+ */
+fn stream_parts<'a>(
+    name: &str,
+    stream: &'a LispExpr,
+) -> Result<Option<(&'a LispExpr, &'a PromiseCell)>, String> {
+    match stream {
+        LispExpr::List(values) if values.is_empty() => Ok(None),
+        LispExpr::List(values) => match &values[..] {
+            [head, LispExpr::Promise(tail)] => Ok(Some((head, tail))),
+            _ => Err(format!("{} expects a stream", name)),
+        },
+        _ => Err(format!("{} expects a stream", name)),
+    }
+}
+
+fn builtin_stream_car(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    match stream_parts("stream-car", &args[0])? {
+        Some((head, _)) => Ok(head.clone()),
+        None => Err("stream-car of empty stream".into()),
+    }
+}
+
+fn builtin_stream_cdr(
+    interpreter: &mut Interpreter,
+    args: &[LispExpr],
+) -> Result<LispExpr, String> {
+    match stream_parts("stream-cdr", &args[0])? {
+        Some((_, tail)) => interpreter.force(tail),
+        None => Err("stream-cdr of empty stream".into()),
+    }
+}
+
+// `(stream-take stream count)` realizes up to count elements into a list.
+fn builtin_stream_take(
+    interpreter: &mut Interpreter,
+    args: &[LispExpr],
+) -> Result<LispExpr, String> {
+    let count = match args[1] {
+        LispExpr::Number(count) if count >= 0 => count,
+        _ => return Err("stream-take expects a stream and a non-negative count".into()),
+    };
+
+    let mut stream = args[0].clone();
+    let mut values = Vec::new();
+
+    while (values.len() as i64) < count {
+        let tail = match stream_parts("stream-take", &stream)? {
+            Some((head, tail)) => {
+                values.push(head.clone());
+                tail.clone()
+            }
+            None => break,
+        };
+
+        // Only force the tail when another element is wanted, so taking n
+        // elements never evaluates past the n-th.
+        if (values.len() as i64) < count {
+            stream = interpreter.force(&tail)?;
+        }
+    }
+
+    Ok(LispExpr::list(values))
+}
+
+// The longest list `iota` or `make-list` builds, so that a huge count fails with
+// an error instead of aborting the process when the allocation fails.
+const MAX_LIST_LENGTH: i64 = 1 << 24;
+
+// `(iota count [start [step]])` with start defaulting to 0 and step to 1.
+fn builtin_iota(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let numbers = as_numbers("iota", args)?;
+
+    let count = numbers[0];
+    let start = numbers.get(1).copied().unwrap_or(0);
+    let step = numbers.get(2).copied().unwrap_or(1);
+
+    if count < 0 {
+        return Err(format!("iota expects a non-negative count, got {}", count));
+    }
+
+    if count > MAX_LIST_LENGTH {
+        return Err(format!(
+            "iota count must be at most {}, got {}",
+            MAX_LIST_LENGTH, count
+        ));
+    }
+
+    (0..count)
+        .map(|index| {
+            index
+                .checked_mul(step)
+                .and_then(|offset| offset.checked_add(start))
+                .map(LispExpr::Number)
+                .ok_or_else(|| "Integer overflow in iota".to_string())
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(LispExpr::list)
+}
+
+// `(make-list count [fill])` with fill defaulting to the empty list.
+fn builtin_make_list(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let fill = args.get(1).cloned().unwrap_or_else(LispExpr::nil);
+
+    match args[0] {
+        LispExpr::Number(count) if count < 0 => Err(format!(
+            "make-list expects a non-negative count, got {}",
+            count
+        )),
+        LispExpr::Number(count) if count > MAX_LIST_LENGTH => Err(format!(
+            "make-list count must be at most {}, got {}",
+            MAX_LIST_LENGTH, count
+        )),
+        LispExpr::Number(count) => Ok(LispExpr::list(vec![fill; count as usize])),
+        _ => Err("make-list expects a count".into()),
+    }
+}
+
+/*
+ * Applies the predicate to each element of the list, returning the elements
+ * paired with whether they satisfied it. Shared by `count` and `remove`.
+ */
+fn test_each(
+    interpreter: &mut Interpreter,
+    name: &str,
+    args: &[LispExpr],
+) -> Result<Vec<(LispExpr, bool)>, String> {
+    match args[1] {
+        LispExpr::List(ref values) => values
+            .iter()
+            .map(|value| {
+                let result = interpreter.apply(&args[0], std::slice::from_ref(value))?;
+
+                Ok((value.clone(), result.is_truthy()))
+            })
+            .collect(),
+        _ => Err(format!("{} expects a predicate and a list", name)),
+    }
+}
+
+/*
+ * Returns the index of the first element satisfying the predicate, or false
+ * when none does. The predicate isn't applied past the first match.
+ */
+fn builtin_list_index(
+    interpreter: &mut Interpreter,
+    args: &[LispExpr],
+) -> Result<LispExpr, String> {
+    for (index, value) in as_list("list-index", &args[1])?.iter().enumerate() {
+        if interpreter
+            .apply(&args[0], std::slice::from_ref(value))?
+            .is_truthy()
+        {
+            return Ok(LispExpr::Number(index as i64));
+        }
+    }
+
+    Ok(LispExpr::Bool(false))
+}
+
+fn builtin_count(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let results = test_each(interpreter, "count", args)?;
+
+    Ok(LispExpr::Number(
+        results.iter().filter(|(_, passed)| *passed).count() as i64,
+    ))
+}
+
+fn builtin_remove(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let results = test_each(interpreter, "remove", args)?;
+
+    Ok(LispExpr::list(
+        results
+            .into_iter()
+            .filter(|(_, passed)| !passed)
+            .map(|(value, _)| value)
+            .collect(),
+    ))
+}
+
+fn fold_arguments<'a>(name: &str, args: &'a [LispExpr]) -> Result<&'a [LispExpr], String> {
+    match args[2] {
+        LispExpr::List(ref values) => Ok(values),
+        _ => Err(format!(
+            "{} expects a procedure, an initial value and a list",
+            name
+        )),
+    }
+}
+
+// `(fold-left - 0 (1 2 3))` is `((0 - 1) - 2) - 3`.
+fn builtin_fold_left(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let values = fold_arguments("fold-left", args)?;
+
+    values.iter().try_fold(args[1].clone(), |acc, value| {
+        interpreter.apply(&args[0], &[acc, value.clone()])
+    })
+}
+
+// `(fold-right - 0 (1 2 3))` is `1 - (2 - (3 - 0))`. Walking the list from the
+// end keeps this iterative as well.
+fn builtin_fold_right(
+    interpreter: &mut Interpreter,
+    args: &[LispExpr],
+) -> Result<LispExpr, String> {
+    let values = fold_arguments("fold-right", args)?;
+
+    values.iter().rev().try_fold(args[1].clone(), |acc, value| {
+        interpreter.apply(&args[0], &[value.clone(), acc])
+    })
+}
+
+fn builtin_is_boolean(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    Ok(LispExpr::Bool(matches!(args[0], LispExpr::Bool(_))))
+}
+
+// Procedures are symbols naming a registered builtin, the same values that
+// `Interpreter::apply` accepts. Special forms like `delay` are not procedures.
+fn builtin_is_procedure(
+    interpreter: &mut Interpreter,
+    args: &[LispExpr],
+) -> Result<LispExpr, String> {
+    Ok(LispExpr::Bool(match args[0] {
+        LispExpr::Symbol(ref symbol) => interpreter.builtins.contains_key(symbol),
+        _ => false,
+    }))
+}
+
+/*
+ * Equality comes in three strengths:
+ *
+ * - `eq?` would be identity: two references to the same object. It is not
+ *   provided since, with only integers, it could not differ from `eqv?`.
+ * - `eqv?` treats numbers, booleans and symbols as equal when they have the
+ *   same type and value, and lists, promises and hash tables as equal only
+ *   when they are the same shared object (or both the empty list). Records
+ *   aren't shared, so two records are `eqv?` when they have the same type and
+ *   their fields are pairwise `eqv?`. Structural comparison is what
+ *   `assert-equal` does.
+ * - `=` compares numbers numerically and errors on anything else. Once floats
+ *   exist `(= 2 2.0)` should be true while `(eqv? 2 2.0)` is false.
+ */
+fn is_eqv(left: &LispExpr, right: &LispExpr) -> bool {
+    match (left, right) {
+        (LispExpr::Number(left), LispExpr::Number(right)) => left == right,
+        (LispExpr::Bool(left), LispExpr::Bool(right)) => left == right,
+        (LispExpr::Symbol(left), LispExpr::Symbol(right)) => left == right,
+        (LispExpr::List(left), LispExpr::List(right)) => {
+            Rc::ptr_eq(left, right) || (left.is_empty() && right.is_empty())
+        }
+        (LispExpr::Promise(left), LispExpr::Promise(right)) => Rc::ptr_eq(left, right),
+        (LispExpr::Hash(left), LispExpr::Hash(right)) => Rc::ptr_eq(left, right),
+        (
+            LispExpr::Record {
+                type_name: left_type,
+                fields: left_fields,
+            },
+            LispExpr::Record {
+                type_name: right_type,
+                fields: right_fields,
+            },
+        ) => {
+            left_type == right_type
+                && left_fields.len() == right_fields.len()
+                && left_fields
+                    .iter()
+                    .zip(right_fields.iter())
+                    .all(|((_, left), (_, right))| is_eqv(left, right))
+        }
+        _ => false,
+    }
+}
+
+fn builtin_is_eqv(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    Ok(LispExpr::Bool(is_eqv(&args[0], &args[1])))
+}
+
+fn builtin_numeric_equal(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let numbers = as_numbers("=", args)?;
+
+    Ok(LispExpr::Bool(
+        numbers.windows(2).all(|pair| pair[0] == pair[1]),
+    ))
+}
+
+/*
+ * Finds the first pair in an association list whose key matches, returning
+ * its index. Shared by the `assoc` family and `del-assoc`.
+ */
+fn find_pair(
+    name: &str,
+    args: &[LispExpr],
+    matches: fn(&LispExpr, &LispExpr) -> bool,
+) -> Result<Option<usize>, String> {
+    let pairs = match args[1] {
+        LispExpr::List(ref pairs) => pairs,
+        _ => return Err(format!("{} expects a key and an association list", name)),
+    };
+
+    for (index, pair) in pairs.iter().enumerate() {
+        match pair {
+            LispExpr::List(pair) if !pair.is_empty() => {
+                if matches(&args[0], &pair[0]) {
+                    return Ok(Some(index));
+                }
+            }
+            _ => return Err(format!("{} expects a list of pairs, got {:?}", name, pair)),
+        }
+    }
+
+    Ok(None)
+}
+
+fn lookup_pair(
+    name: &str,
+    args: &[LispExpr],
+    matches: fn(&LispExpr, &LispExpr) -> bool,
+) -> Result<LispExpr, String> {
+    match (find_pair(name, args, matches)?, &args[1]) {
+        (Some(index), LispExpr::List(pairs)) => Ok(pairs[index].clone()),
+        _ => Ok(LispExpr::Bool(false)),
+    }
+}
+
+fn builtin_assoc(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    lookup_pair("assoc", args, |key, candidate| key == candidate)
+}
+
+// There is no separate `eq?` (see `is_eqv`), so `assq` matches like `assv`.
+fn builtin_assq(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    lookup_pair("assq", args, is_eqv)
+}
+
+fn builtin_assv(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    lookup_pair("assv", args, is_eqv)
+}
+
+// Removes the first pair whose key is structurally equal, like `assoc`.
+fn builtin_del_assoc(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    match (
+        find_pair("del-assoc", args, |key, candidate| key == candidate)?,
+        &args[1],
+    ) {
+        (Some(index), LispExpr::List(pairs)) => {
+            let mut pairs = pairs.to_vec();
+            pairs.remove(index);
+
+            Ok(LispExpr::list(pairs))
+        }
+        _ => Ok(args[1].clone()),
+    }
+}
+
+fn as_hash(name: &str, expr: &LispExpr) -> Result<Rc<RefCell<HashMap<HashKey, LispExpr>>>, String> {
+    match expr {
+        LispExpr::Hash(hash) => Ok(hash.clone()),
+        _ => Err(format!("{} expects a hash table", name)),
+    }
+}
+
+fn builtin_make_hash(_: &mut Interpreter, _: &[LispExpr]) -> Result<LispExpr, String> {
+    Ok(LispExpr::Hash(Rc::new(RefCell::new(HashMap::new()))))
+}
+
+// `hash-set!` and `hash-remove!` return the table itself so updates can be
+// chained, e.g. `(hash-ref (hash-set! (make-hash) k 1) k)`.
+fn builtin_hash_set(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let hash = as_hash("hash-set!", &args[0])?;
+    hash.borrow_mut()
+        .insert(HashKey::from_expr(&args[1])?, args[2].clone());
+
+    Ok(LispExpr::Hash(hash))
+}
+
+fn builtin_hash_ref(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let hash = as_hash("hash-ref", &args[0])?;
+    let key = HashKey::from_expr(&args[1])?;
+    let value = hash.borrow().get(&key).cloned();
+
+    match (value, args.get(2)) {
+        (Some(value), _) => Ok(value),
+        (None, Some(default)) => Ok(default.clone()),
+        (None, None) => Err(format!("Key not found in hash: {:?}", args[1])),
+    }
+}
+
+fn builtin_hash_remove(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let hash = as_hash("hash-remove!", &args[0])?;
+    hash.borrow_mut().remove(&HashKey::from_expr(&args[1])?);
+
+    Ok(LispExpr::Hash(hash))
+}
+
+/*
+ * The entries of a hash table sorted by key, numbers before symbols, so the
+ * iteration builtins below give the same order every time.
+ */
+fn hash_entries(name: &str, args: &[LispExpr]) -> Result<Vec<(LispExpr, LispExpr)>, String> {
+    let hash = as_hash(name, &args[0])?;
+    let mut entries: Vec<_> = hash
+        .borrow()
+        .iter()
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(entries
+        .into_iter()
+        .map(|(key, value)| (key.to_expr(), value))
+        .collect())
+}
+
+fn builtin_hash_keys(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let entries = hash_entries("hash-keys", args)?;
+
+    Ok(LispExpr::list(
+        entries.into_iter().map(|(key, _)| key).collect(),
+    ))
+}
+
+fn builtin_hash_values(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let entries = hash_entries("hash-values", args)?;
+
+    Ok(LispExpr::list(
+        entries.into_iter().map(|(_, value)| value).collect(),
+    ))
+}
+
+fn builtin_hash_to_list(_: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let entries = hash_entries("hash->list", args)?;
+
+    Ok(LispExpr::list(
+        entries
+            .into_iter()
+            .map(|(key, value)| LispExpr::list(vec![key, value]))
+            .collect(),
+    ))
+}
+
+// `(hash-map f h)` calls `f` with each key and value, returning the results.
+fn builtin_hash_map(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    hash_entries("hash-map", &args[1..])?
+        .into_iter()
+        .map(|(key, value)| interpreter.apply(&args[0], &[key, value]))
+        .collect::<Result<Vec<_>, _>>()
+        .map(LispExpr::list)
+}
+
+// There are no strings, so `(version)` is `(major minor patch)`, e.g. `(0 1 0)`.
+fn builtin_version(_: &mut Interpreter, _: &[LispExpr]) -> Result<LispExpr, String> {
+    let parts = [
+        env!("CARGO_PKG_VERSION_MAJOR"),
+        env!("CARGO_PKG_VERSION_MINOR"),
+        env!("CARGO_PKG_VERSION_PATCH"),
+    ];
+
+    Ok(LispExpr::list(
+        parts
+            .iter()
+            .map(|part| LispExpr::Number(part.parse().unwrap_or(0)))
+            .collect(),
+    ))
+}
+
+/*
+ * Documentation shown by `help`: the accepted argument counts (matching the
+ * builtin's own `check_arity` call) and a one-line summary.
+ */
+struct BuiltinDoc {
+    min_args: usize,
+    max_args: Option<usize>,
+    summary: String,
+}
+
+fn describe_arity(min: usize, max: Option<usize>) -> String {
+    let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+
+    match max {
+        Some(max) if min == max => format!("exactly {} {}", min, plural(min)),
+        Some(max) => format!("{} to {} arguments", min, max),
+        None if min == 0 => "any number of arguments".into(),
+        None => format!("at least {} {}", min, plural(min)),
+    }
+}
+
+// `help` writes its description like `pretty-print`, as there are no strings
+// to return it in.
+fn builtin_help(interpreter: &mut Interpreter, args: &[LispExpr]) -> Result<LispExpr, String> {
+    let name = match args[0] {
+        LispExpr::Symbol(ref name) => name,
+        _ => return Err("help expects a builtin name".into()),
+    };
+
+    let description = match interpreter.docs.get(name) {
+        Some(doc) => format!(
+            "{}: takes {}. {}",
+            name,
+            describe_arity(doc.min_args, doc.max_args),
+            doc.summary
+        ),
+        // Builtins an embedder registered without documentation.
+        None if interpreter.builtins.contains_key(name) => {
+            format!("{}: builtin, no documentation available", name)
+        }
+        None => return Err(format!("help: {} is not a builtin", name)),
+    };
+
+    writeln!(interpreter.output, "{}", description)
+        .map_err(|error| format!("Could not write output: {}", error))?;
+
+    Ok(LispExpr::nil())
+}
+
+// How many evaluation steps run between checks of the wall-clock deadline.
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
+
+// How deeply builtin calls and nested expressions may go before evaluation
+// fails. Both are kept low enough to fit a 2 MiB thread stack in debug builds.
+const MAX_CALL_DEPTH: usize = 256;
+const MAX_EXPRESSION_DEPTH: usize = 512;
+
+pub struct Interpreter {
+    builtins: HashMap<String, Builtin>,
+    // Arity and summaries for `help`, for builtins registered with them.
+    docs: HashMap<String, BuiltinDoc>,
+    // Dynamic parameters, each a stack whose top is the current value.
+    params: HashMap<String, Vec<LispExpr>>,
+    output: Box<dyn Write>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    steps: u64,
+    // How many builtin calls are currently in progress.
+    call_depth: usize,
+    // How many nested lists are currently being evaluated.
+    expression_depth: usize,
+    // Call counts per builtin and special form, while profiling is enabled.
+    profile: Option<HashMap<String, u64>>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        let mut interpreter = Interpreter {
+            builtins: HashMap::new(),
+            docs: HashMap::new(),
+            params: HashMap::new(),
+            output: Box::new(io::stdout()),
+            timeout: None,
+            deadline: None,
+            steps: 0,
+            call_depth: 0,
+            expression_depth: 0,
+            profile: None,
+        };
+
+        interpreter.register_builtins();
+
+        interpreter
+    }
+
+    /*
+     * Clears all builtins registered after construction and all dynamic
+     * parameters, returning to the state `Interpreter::new` produces. The output
+     * writer and timeout are configuration and are kept.
+     */
+    pub fn reset(&mut self) {
+        self.builtins.clear();
+        self.docs.clear();
+        self.params.clear();
+        self.register_builtins();
+    }
+
+    fn register_builtins(&mut self) {
+        self.register_documented("+", 1, None, "Adds the numbers.", builtin_add);
+        self.register_documented(
+            "-",
+            1,
+            None,
+            "Negates one number, or subtracts the rest from the first.",
+            builtin_subtract,
+        );
+        self.register_documented(
+            "/",
+            2,
+            None,
+            "Divides the first number by each of the rest.",
+            builtin_divide,
+        );
+        self.register_documented("*", 2, None, "Multiplies the numbers.", builtin_multiply);
+        self.register_documented(
+            "force",
+            1,
+            Some(1),
+            "Evaluates a promise, memoizing the result.",
+            builtin_force,
+        );
+        self.register_documented(
+            "identity",
+            1,
+            Some(1),
+            "Returns its argument.",
+            builtin_identity,
+        );
+        self.register_documented(
+            "zero?",
+            1,
+            Some(1),
+            "Tests whether a number is zero.",
+            builtin_is_zero,
+        );
+        self.register_documented(
+            "positive?",
+            1,
+            Some(1),
+            "Tests whether a number is greater than zero.",
+            builtin_is_positive,
+        );
+        self.register_documented(
+            "negative?",
+            1,
+            Some(1),
+            "Tests whether a number is less than zero.",
+            builtin_is_negative,
+        );
+        self.register_documented(
+            "even?",
+            1,
+            Some(1),
+            "Tests whether a number is even.",
+            builtin_is_even,
+        );
+        self.register_documented(
+            "odd?",
+            1,
+            Some(1),
+            "Tests whether a number is odd.",
+            builtin_is_odd,
+        );
+        self.register_documented(
+            "take",
+            2,
+            Some(2),
+            "Returns the first count elements of a list.",
+            builtin_take,
+        );
+        self.register_documented(
+            "drop",
+            2,
+            Some(2),
+            "Returns a list without its first count elements.",
+            builtin_drop,
+        );
+        self.register_documented(
+            "assert",
+            1,
+            Some(1),
+            "Fails unless the value is true.",
+            builtin_assert,
+        );
+        self.register_documented(
+            "assert-equal",
+            2,
+            Some(2),
+            "Fails unless the two values are equal.",
+            builtin_assert_equal,
+        );
+        self.register_documented(
+            "list?",
+            1,
+            Some(1),
+            "Tests whether a value is a list.",
+            builtin_is_list,
+        );
+        self.register_documented(
+            "pair?",
+            1,
+            Some(1),
+            "Tests whether a value is a non-empty list.",
+            builtin_is_pair,
+        );
+        self.register_documented(
+            "max-by",
+            2,
+            Some(2),
+            "Returns the list element with the largest key.",
+            builtin_max_by,
+        );
+        self.register_documented(
+            "min-by",
+            2,
+            Some(2),
+            "Returns the list element with the smallest key.",
+            builtin_min_by,
+        );
+        self.register_documented(
+            "pretty-print",
+            1,
+            Some(1),
+            "Writes a value as indented lisp source.",
+            builtin_pretty_print,
+        );
+        self.register_documented(
+            "define-param",
+            2,
+            Some(2),
+            "Defines a dynamic parameter with a value.",
+            builtin_define_param,
+        );
+        self.register_documented(
+            "get-param",
+            1,
+            Some(1),
+            "Returns the current value of a parameter.",
+            builtin_get_param,
+        );
+        self.register_documented(
+            "zip",
+            2,
+            None,
+            "Combines lists element-wise into a list of lists.",
+            builtin_zip,
+        );
+        self.register_documented(
+            "unzip",
+            1,
+            Some(1),
+            "Splits a list of lists into a list per position.",
+            builtin_unzip,
+        );
+        self.register_documented(
+            "fold-left",
+            3,
+            Some(3),
+            "Folds a list from the front with an initial value.",
+            builtin_fold_left,
+        );
+        self.register_documented(
+            "fold-right",
+            3,
+            Some(3),
+            "Folds a list from the back with an initial value.",
+            builtin_fold_right,
+        );
+        self.register_documented(
+            "boolean?",
+            1,
+            Some(1),
+            "Tests whether a value is true or false.",
+            builtin_is_boolean,
+        );
+        self.register_documented(
+            "procedure?",
+            1,
+            Some(1),
+            "Tests whether a value names a builtin.",
+            builtin_is_procedure,
+        );
+        self.register_documented(
+            "iota",
+            1,
+            Some(3),
+            "Returns count numbers from start, stepping by step.",
+            builtin_iota,
+        );
+        self.register_documented(
+            "eqv?",
+            2,
+            Some(2),
+            "Tests whether two values are the same object.",
+            builtin_is_eqv,
+        );
+        self.register_documented(
+            "=",
+            1,
+            None,
+            "Tests whether all the numbers are equal.",
+            builtin_numeric_equal,
+        );
+        self.register_documented(
+            "assoc",
+            2,
+            Some(2),
+            "Finds the pair with an equal key in an alist.",
+            builtin_assoc,
+        );
+        self.register_documented(
+            "assq",
+            2,
+            Some(2),
+            "Finds the pair with an eqv? key in an alist.",
+            builtin_assq,
+        );
+        self.register_documented(
+            "assv",
+            2,
+            Some(2),
+            "Finds the pair with an eqv? key in an alist.",
+            builtin_assv,
+        );
+        self.register_documented(
+            "del-assoc",
+            2,
+            Some(2),
+            "Removes the first pair with an equal key.",
+            builtin_del_assoc,
+        );
+        self.register_documented(
+            "count",
+            2,
+            Some(2),
+            "Counts the list elements satisfying a predicate.",
+            builtin_count,
+        );
+        self.register_documented(
+            "remove",
+            2,
+            Some(2),
+            "Returns the list elements failing a predicate.",
+            builtin_remove,
+        );
+        self.register_documented(
+            "isqrt",
+            1,
+            Some(1),
+            "Returns the floor of the square root.",
+            builtin_isqrt,
+        );
+        self.register_documented(
+            "exact-integer-sqrt",
+            1,
+            Some(1),
+            "Returns the square root and remainder.",
+            builtin_exact_integer_sqrt,
+        );
+        self.register_documented(
+            "make-list",
+            1,
+            Some(2),
+            "Returns count copies of a fill value.",
+            builtin_make_list,
+        );
+        self.register_documented(
+            "last",
+            1,
+            Some(1),
+            "Returns the last element of a list.",
+            builtin_last,
+        );
+        self.register_documented(
+            "last-pair",
+            1,
+            Some(1),
+            "Returns a list of the last element of a list.",
+            builtin_last_pair,
+        );
+        self.register_documented(
+            "flatten",
+            1,
+            Some(1),
+            "Returns the atoms of a nested list in one list.",
+            builtin_flatten,
+        );
+        self.register_documented(
+            "map",
+            2,
+            None,
+            "Applies a procedure across lists element-wise.",
+            builtin_map,
+        );
+        self.register_documented(
+            "for-each",
+            2,
+            None,
+            "Applies a procedure across lists for its effects.",
+            builtin_for_each,
+        );
+        self.register_documented(
+            "stream-car",
+            1,
+            Some(1),
+            "Returns the first element of a stream.",
+            builtin_stream_car,
+        );
+        self.register_documented(
+            "stream-cdr",
+            1,
+            Some(1),
+            "Forces and returns the rest of a stream.",
+            builtin_stream_cdr,
+        );
+        self.register_documented(
+            "stream-take",
+            2,
+            Some(2),
+            "Returns the first count elements of a stream.",
+            builtin_stream_take,
+        );
+        self.register_documented(
+            "list-copy",
+            1,
+            Some(1),
+            "Returns a shallow copy of a list.",
+            builtin_list_copy,
+        );
+        self.register_documented(
+            "floor/",
+            2,
+            Some(2),
+            "Divides rounding down, returning quotient and remainder.",
+            builtin_floor_divide,
+        );
+        self.register_documented(
+            "ceiling/",
+            2,
+            Some(2),
+            "Divides rounding up, returning quotient and remainder.",
+            builtin_ceiling_divide,
+        );
+        self.register_documented(
+            "round/",
+            2,
+            Some(2),
+            "Divides rounding to nearest, returning quotient and remainder.",
+            builtin_round_divide,
+        );
+        self.register_documented(
+            "truncate/",
+            2,
+            Some(2),
+            "Divides rounding toward zero, returning quotient and remainder.",
+            builtin_truncate_divide,
+        );
+        self.register_documented(
+            "version",
+            0,
+            Some(0),
+            "Returns the interpreter version as (major minor patch).",
+            builtin_version,
+        );
+        self.register_documented(
+            "print",
+            0,
+            None,
+            "Writes the values separated by spaces.",
+            builtin_print,
+        );
+        self.register_documented(
+            "println",
+            0,
+            None,
+            "Writes the values separated by spaces, then a newline.",
+            builtin_println,
+        );
+        self.register_documented(
+            "square",
+            1,
+            Some(1),
+            "Returns a number times itself.",
+            builtin_square,
+        );
+        self.register_documented(
+            "cube",
+            1,
+            Some(1),
+            "Returns a number to the third power.",
+            builtin_cube,
+        );
+        self.register_documented(
+            "list-index",
+            2,
+            Some(2),
+            "Returns the index of the first element satisfying a predicate.",
+            builtin_list_index,
+        );
+        self.register_documented("help", 1, Some(1), "Describes a builtin.", builtin_help);
+        self.register_documented(
+            "make-hash",
+            0,
+            Some(0),
+            "Returns a new empty hash table.",
+            builtin_make_hash,
+        );
+        self.register_documented(
+            "hash-set!",
+            3,
+            Some(3),
+            "Stores a value under a key in a hash table.",
+            builtin_hash_set,
+        );
+        self.register_documented(
+            "hash-ref",
+            2,
+            Some(3),
+            "Looks up a key, with an optional default.",
+            builtin_hash_ref,
+        );
+        self.register_documented(
+            "hash-remove!",
+            2,
+            Some(2),
+            "Removes a key from a hash table.",
+            builtin_hash_remove,
+        );
+        self.register_documented(
+            "hash-keys",
+            1,
+            Some(1),
+            "Returns the keys of a hash table.",
+            builtin_hash_keys,
+        );
+        self.register_documented(
+            "hash-values",
+            1,
+            Some(1),
+            "Returns the values of a hash table, in key order.",
+            builtin_hash_values,
+        );
+        self.register_documented(
+            "hash->list",
+            1,
+            Some(1),
+            "Returns the (key value) pairs of a hash table.",
+            builtin_hash_to_list,
+        );
+        self.register_documented(
+            "hash-map",
+            2,
+            Some(2),
+            "Calls a procedure with each key and value.",
+            builtin_hash_map,
+        );
+    }
+
+    /*
+     * Registers a builtin function under `name`, replacing any existing builtin
+     * with the same name. Special forms such as `delay` are checked before the
+     * registry and cannot be overridden.
+     */
+    pub fn register<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&mut Interpreter, &[LispExpr]) -> Result<LispExpr, String> + 'static,
+    {
+        self.docs.remove(name);
+        self.builtins.insert(name.into(), Rc::new(func));
+    }
+
+    /*
+     * Registers a builtin like `register`, along with the argument counts it
+     * accepts and a one-line summary for `help` to show. Calls with any other
+     * number of arguments fail before the builtin runs.
+     */
+    pub fn register_documented<F>(
+        &mut self,
+        name: &str,
+        min_args: usize,
+        max_args: Option<usize>,
+        summary: &str,
+        func: F,
+    ) where
+        F: Fn(&mut Interpreter, &[LispExpr]) -> Result<LispExpr, String> + 'static,
+    {
+        self.register(name, func);
+        self.docs.insert(
+            name.into(),
+            BuiltinDoc {
+                min_args,
+                max_args,
+                summary: summary.into(),
+            },
+        );
+    }
+
+    /*
+     * Calls a procedure value with already evaluated arguments. Symbols evaluate
+     * to themselves, so a procedure is the symbol naming a registered builtin.
+     */
+    pub fn apply(&mut self, func: &LispExpr, args: &[LispExpr]) -> Result<LispExpr, String> {
+        let builtin = match func {
+            LispExpr::Symbol(symbol) => self.builtins.get(symbol).cloned(),
+            _ => None,
+        };
+
+        match (builtin, func) {
+            (Some(builtin), LispExpr::Symbol(symbol)) => self.call_builtin(symbol, builtin, args),
+            _ => Err(format!("{:?} is not a procedure", func)),
+        }
+    }
+
+    /*
+     * Sets the value of a dynamic parameter, as `define-param` does.
+     */
+    pub fn define_param(&mut self, name: &str, value: LispExpr) {
+        // Redefining only replaces the base value, so any enclosing
+        // `parameterize` bindings stay in effect until they are popped.
+        let stack = self.params.entry(name.into()).or_default();
+
+        match stack.first_mut() {
+            Some(base) => *base = value,
+            None => stack.push(value),
+        }
+    }
+
+    /*
+     * Every builtin call, direct or through `apply`, goes through here. Nested
+     * calls are limited to `MAX_CALL_DEPTH`, so a promise or stream that forces
+     * itself fails with an error instead of overflowing the stack. Documented
+     * builtins have their argument counts checked here rather than in each one.
+     */
+    fn call_builtin(
+        &mut self,
+        name: &str,
+        builtin: Builtin,
+        args: &[LispExpr],
+    ) -> Result<LispExpr, String> {
+        self.record_call(name);
+
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err("Maximum call depth exceeded".into());
+        }
+
+        if let Some(doc) = self.docs.get(name) {
+            check_arity(name, args, doc.min_args, doc.max_args)?;
+        }
+
+        self.call_depth += 1;
+        let result = builtin(self, args);
+        self.call_depth -= 1;
+
+        result
+    }
+
+    /*
+     * Starts counting calls to each builtin and special form, discarding any
+     * earlier counts, or stops counting when `enabled` is false.
+     */
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = if enabled { Some(HashMap::new()) } else { None };
+    }
+
+    fn record_call(&mut self, name: &str) {
+        if let Some(ref mut profile) = self.profile {
+            *profile.entry(name.into()).or_insert(0) += 1;
+        }
+    }
+
+    /*
+     * Writes the call counts gathered since profiling was enabled to the output
+     * writer, most called first, and returns the total number of calls.
+     */
+    pub fn write_profile(&mut self) -> Result<u64, String> {
+        let mut counts: Vec<(String, u64)> = match self.profile {
+            Some(ref profile) => profile
+                .iter()
+                .map(|(name, count)| (name.clone(), *count))
+                .collect(),
+            None => return Err("Profiling is not enabled".into()),
+        };
+        counts.sort_by(|(a_name, a_count), (b_name, b_count)| {
+            b_count.cmp(a_count).then_with(|| a_name.cmp(b_name))
+        });
+
+        for (name, count) in &counts {
+            writeln!(self.output, "{:>8}  {}", count, name)
+                .map_err(|error| format!("Could not write output: {}", error))?;
+        }
+
+        Ok(counts.iter().map(|(_, count)| count).sum())
+    }
+
+    /*
+     * Replaces the writer that printing builtins such as `pretty-print` send
+     * their output to. Defaults to stdout.
+     */
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /*
+     * Limits how long each top-level call to `evaluate` may run before failing
+     * with "Evaluation timed out". `None` disables the limit.
+     */
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn evaluate(&mut self, ast: LispExpr) -> Result<LispExpr, String> {
+        // The outermost call starts the clock; nested calls share its deadline.
+        let is_top_level = self.deadline.is_none();
+
+        if is_top_level {
+            self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+            self.steps = 0;
+        }
+
+        let result = self.evaluate_expr(ast);
+
+        if is_top_level {
+            self.deadline = None;
+        }
+
+        result
+    }
+
+    fn check_deadline(&mut self) -> Result<(), String> {
+        self.steps += 1;
+
+        if self.steps.is_multiple_of(TIMEOUT_CHECK_INTERVAL) {
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    return Err("Evaluation timed out".into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /*
+     * Nested lists are limited to `MAX_EXPRESSION_DEPTH` levels of evaluation,
+     * so deeply nested source fails with an error instead of overflowing the
+     * stack.
+     */
+    fn evaluate_expr(&mut self, ast: LispExpr) -> Result<LispExpr, String> {
+        self.check_deadline()?;
+
+        let values = match &ast {
+            LispExpr::List(values) => values.clone(),
+            LispExpr::Number(_) => return Ok(ast),
+            LispExpr::Bool(_) => return Ok(ast),
+            LispExpr::Symbol(_) => return Ok(ast),
+            LispExpr::Promise(_) => return Ok(ast),
+            LispExpr::Hash(_) => return Ok(ast),
+            LispExpr::Record { .. } => return Ok(ast),
+        };
+
+        if self.expression_depth >= MAX_EXPRESSION_DEPTH {
+            return Err("Maximum expression depth exceeded".into());
+        }
+
+        self.expression_depth += 1;
+        let result = self.evaluate_list(values);
+        self.expression_depth -= 1;
+
+        result
+    }
+
+    fn evaluate_list(&mut self, values: Rc<Vec<LispExpr>>) -> Result<LispExpr, String> {
+        match values.first() {
+            Some(LispExpr::Symbol(symbol)) => {
+                if let Some(result) = self.evaluate_special_form(symbol, &values) {
+                    return result;
+                }
+
+                if let Some(builtin) = self.builtins.get(symbol).cloned() {
+                    let args = self.evaluate_arguments(&values[1..])?;
+
+                    self.call_builtin(symbol, builtin, &args)
+                } else {
+                    Ok(LispExpr::List(values))
+                }
+            }
+            _ => Ok(LispExpr::List(values)),
+        }
+    }
+
+    /*
+     * Evaluates `values` if it is a special form, returning `None` otherwise.
+     * Kept apart from `evaluate_list` so that the stack frame of every nested
+     * call stays small.
+     */
+    fn evaluate_special_form(
+        &mut self,
+        symbol: &str,
+        values: &[LispExpr],
+    ) -> Option<Result<LispExpr, String>> {
+        let check = |min, max| check_arity(symbol, &values[1..], min, max);
+
+        let result = match symbol {
+            "delay" => check(1, Some(1)).map(|_| {
+                LispExpr::Promise(Rc::new(RefCell::new(PromiseState::Delayed(
+                    values[1].clone(),
+                ))))
+            }),
+            // `(cons-stream head tail)` delays `tail` like `delay`.
+            "cons-stream" => check(2, Some(2))
+                .and_then(|_| self.evaluate(values[1].clone()))
+                .map(|head| {
+                    let tail = PromiseState::Delayed(values[2].clone());
+
+                    LispExpr::list(vec![head, LispExpr::Promise(Rc::new(RefCell::new(tail)))])
+                }),
+            "define-record" => {
+                check(2, Some(2)).and_then(|_| self.define_record(&values[1], &values[2]))
+            }
+            "parameterize" => {
+                check(1, None).and_then(|_| self.parameterize(&values[1], &values[2..]))
+            }
+            "case" => check(1, None).and_then(|_| self.case(&values[1], &values[2..])),
+            "define-values" => {
+                check(2, Some(2)).and_then(|_| self.define_values(&values[1], &values[2]))
+            }
+            _ => return None,
+        };
+
+        self.record_call(symbol);
+
+        Some(result)
+    }
+
+    /*
+     * Evaluates the arguments of a call. An argument written `(... expr)` is a
+     * splice: `expr` must evaluate to a list, whose elements are passed in its
+     * place, so `(+ 1 (... (iota 3)) 10)` is `(+ 1 0 1 2 10)`.
+     */
+    fn evaluate_arguments(&mut self, asts: &[LispExpr]) -> Result<Vec<LispExpr>, String> {
+        let mut args = Vec::with_capacity(asts.len());
+
+        // Arguments are evaluated strictly left to right, and evaluation stops
+        // at the first one that fails, so side effects happen in source order.
+        for ast in asts {
+            match ast {
+                LispExpr::List(values) if matches!(values.first(), Some(LispExpr::Symbol(symbol)) if symbol == "...") => {
+                    args.extend(self.evaluate_splice(&values[1..])?.iter().cloned())
+                }
+                _ => args.push(self.evaluate(ast.clone())?),
+            }
+        }
+
+        Ok(args)
+    }
+
+    fn evaluate_splice(&mut self, args: &[LispExpr]) -> Result<Rc<Vec<LispExpr>>, String> {
+        check_arity("...", args, 1, Some(1))?;
+
+        match self.evaluate(args[0].clone())? {
+            LispExpr::List(ref spliced) => Ok(spliced.clone()),
+            value => Err(format!("... expects a list, got {:?}", value)),
+        }
+    }
+
+    /*
+     * Parses and evaluates every top-level form in `src`, returning the value of
+     * the final form or `None` if there are no forms. Definitions made by the
+     * forms stay in this interpreter.
+     */
+    pub fn evaluate_source(&mut self, src: &str) -> Result<Option<LispExpr>, String> {
+        let forms = tokenize(src)
+            .and_then(|tokens| Parser::new(tokens).parse_all())
+            .map_err(|error| error.to_string())?;
+
+        if forms.is_empty() {
+            return Ok(None);
+        }
+
+        self.evaluate_all(forms).map(Some)
+    }
+
+    /*
+     * Evaluates each expression in order and returns the value of the last one,
+     * or the empty list when there are none. Stops at the first error, naming
+     * the (1-based) index of the form that failed.
+     */
+    pub fn evaluate_all(&mut self, exprs: Vec<LispExpr>) -> Result<LispExpr, String> {
+        let mut result = LispExpr::nil();
+
+        for (index, expr) in exprs.into_iter().enumerate() {
+            result = self
+                .evaluate(expr)
+                .map_err(|error| format!("Error in form {}: {}", index + 1, error))?;
+        }
+
+        Ok(result)
+    }
+
+    /*
+     * Handles `(define-record point (x y))` by registering `make-point`,
+     * `point?`, and an accessor `point-x` for every field as builtins.
+     */
+    fn define_record(&mut self, name: &LispExpr, fields: &LispExpr) -> Result<LispExpr, String> {
+        let type_name = match name {
+            LispExpr::Symbol(name) => name.clone(),
+            _ => return Err("define-record expects a record name symbol".into()),
+        };
+
+        let field_names = match fields {
+            LispExpr::List(fields) => fields
+                .iter()
+                .map(|field| match field {
+                    LispExpr::Symbol(field) => Ok(field.clone()),
+                    _ => Err("define-record fields must be symbols".to_string()),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Err("define-record expects a list of fields".into()),
+        };
+
+        // A repeated field would get two constructor slots but one accessor.
+        if let Some(index) =
+            (1..field_names.len()).find(|&index| field_names[..index].contains(&field_names[index]))
+        {
+            return Err(format!(
+                "define-record field {} is listed more than once",
+                field_names[index]
+            ));
+        }
+
+        let constructor = format!("make-{}", type_name);
+        let predicate = format!("{}?", type_name);
+        let accessors: Vec<String> = field_names
+            .iter()
+            .map(|field| format!("{}-{}", type_name, field))
+            .collect();
+
+        // Registering would silently replace a builtin such as `list?`, so
+        // nothing is registered if any of the names is taken.
+        if let Some(taken) = std::iter::once(&constructor)
+            .chain(std::iter::once(&predicate))
+            .chain(accessors.iter())
+            .find(|name| self.builtins.contains_key(*name))
+        {
+            return Err(format!(
+                "define-record {} would redefine the builtin {}",
+                type_name, taken
+            ));
+        }
+
+        let constructor_type = type_name.clone();
+        let constructor_fields = field_names.clone();
+
+        let count = field_names.len();
+        let summary = format!("Constructs a {} record.", type_name);
+
+        self.register_documented(
+            &constructor,
+            count,
+            Some(count),
+            &summary,
+            move |_, args| {
+                Ok(LispExpr::Record {
+                    type_name: constructor_type.clone(),
+                    fields: constructor_fields
+                        .iter()
+                        .cloned()
+                        .zip(args.iter().cloned())
+                        .collect(),
+                })
+            },
+        );
+
+        let predicate_type = type_name.clone();
+
+        let summary = format!("Tests whether a value is a {} record.", type_name);
+
+        self.register_documented(&predicate, 1, Some(1), &summary, move |_, args| {
+            Ok(LispExpr::Bool(matches!(
+                args[0],
+                LispExpr::Record { ref type_name, .. } if *type_name == predicate_type
+            )))
+        });
+
+        for (index, accessor) in accessors.into_iter().enumerate() {
+            let accessor_name = accessor.clone();
+            let accessor_type = type_name.clone();
+
+            let summary = format!(
+                "Returns the {} field of a {} record.",
+                field_names[index], type_name
+            );
+
+            self.register_documented(&accessor, 1, Some(1), &summary, move |_, args| {
+                match args[0] {
+                    LispExpr::Record {
+                        ref type_name,
+                        ref fields,
+                    } if *type_name == accessor_type => Ok(fields[index].1.clone()),
+                    _ => Err(format!(
+                        "{} expects a {} record",
+                        accessor_name, accessor_type
+                    )),
+                }
+            });
+        }
+
+        Ok(LispExpr::nil())
+    }
+
+    /*
+     * Handles `(case key ((datum ...) body ...) ... (else body ...))`. The key
+     * is evaluated and compared against the unevaluated datums with `eqv?`; the
+     * first matching clause's body is evaluated, returning its last value. With
+     * no match and no `else` clause the result is the empty list.
+     */
+    fn case(&mut self, key: &LispExpr, clauses: &[LispExpr]) -> Result<LispExpr, String> {
+        let key = self.evaluate(key.clone())?;
+
+        for (index, clause) in clauses.iter().enumerate() {
+            let (datums, body) = match clause {
+                LispExpr::List(clause) if !clause.is_empty() => (&clause[0], &clause[1..]),
+                _ => return Err("case clause must be ((datum ...) body ...)".into()),
+            };
+
+            let matched = match datums {
+                LispExpr::Symbol(symbol) if symbol == "else" => {
+                    if index + 1 != clauses.len() {
+                        return Err("case else clause must be last".into());
+                    }
+
+                    true
+                }
+                LispExpr::List(datums) => datums.iter().any(|datum| is_eqv(&key, datum)),
+                _ => return Err("case clause must start with a list of datums".into()),
+            };
+
+            if matched {
+                let mut result = LispExpr::nil();
+
+                for expr in body {
+                    result = self.evaluate(expr.clone())?;
+                }
+
+                return Ok(result);
+            }
+        }
+
+        Ok(LispExpr::nil())
+    }
+
+    /*
+     * Handles `(define-values (q r) (floor/ 7 2))`. Multiple values are returned
+     * as a list, so the producer's list is destructured and each name defined
+     * as a parameter, as `define-param` would.
+     */
+    fn define_values(&mut self, names: &LispExpr, producer: &LispExpr) -> Result<LispExpr, String> {
+        let names = match names {
+            LispExpr::List(names) => names
+                .iter()
+                .map(|name| match name {
+                    LispExpr::Symbol(name) => Ok(name.clone()),
+                    _ => Err("define-values names must be symbols".to_string()),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Err("define-values expects a list of names".into()),
+        };
+
+        let values = match self.evaluate(producer.clone())? {
+            LispExpr::List(ref values) => values.clone(),
+            value => {
+                return Err(format!(
+                    "define-values expects a list of values, got {:?}",
+                    value
+                ))
+            }
+        };
+
+        if names.len() != values.len() {
+            return Err(format!(
+                "define-values expects {} {}, got {}",
+                names.len(),
+                if names.len() == 1 { "value" } else { "values" },
+                values.len()
+            ));
+        }
+
+        for (name, value) in names.iter().zip(values.iter()) {
+            self.define_param(name, value.clone());
+        }
+
+        Ok(LispExpr::nil())
+    }
+
+    /*
+     * Evaluates `body` with each `(param value)` binding pushed onto that
+     * parameter's stack. The bindings are popped again even if the body fails.
+     */
+    fn parameterize(&mut self, bindings: &LispExpr, body: &[LispExpr]) -> Result<LispExpr, String> {
+        let bindings = match bindings {
+            LispExpr::List(bindings) => bindings,
+            _ => return Err("parameterize bindings must be a list".into()),
+        };
+
+        let mut values = Vec::new();
+
+        for binding in bindings.iter() {
+            match binding {
+                LispExpr::List(pair) => match &pair[..] {
+                    [LispExpr::Symbol(name), value] => {
+                        if !self.params.contains_key(name) {
+                            return Err(format!("Undefined parameter: {}", name));
                         }
+
+                        values.push((name.clone(), self.evaluate(value.clone())?));
                     }
-                    _ => Ok(LispExpr::List(values)),
+                    _ => return Err("parameterize binding must be (param value)".into()),
                 },
-                _ => Ok(LispExpr::List(values)),
+                _ => return Err("parameterize binding must be (param value)".into()),
+            }
+        }
+
+        for (name, value) in &values {
+            self.params.get_mut(name).unwrap().push(value.clone());
+        }
+
+        let result = body
+            .iter()
+            .try_fold(LispExpr::nil(), |_, expr| self.evaluate(expr.clone()));
+
+        for (name, _) in &values {
+            self.params.get_mut(name).unwrap().pop();
+        }
+
+        result
+    }
+
+    fn force(&mut self, promise: &Rc<RefCell<PromiseState>>) -> Result<LispExpr, String> {
+        let expr = match *promise.borrow() {
+            PromiseState::Forced(ref value) => return Ok(value.clone()),
+            PromiseState::Delayed(ref expr) => expr.clone(),
+        };
+
+        // Only memoize once the delayed expression evaluates successfully, so a
+        // failed force can be retried.
+        let value = self.evaluate(expr)?;
+        *promise.borrow_mut() = PromiseState::Forced(value.clone());
+
+        Ok(value)
+    }
+}
+
+/*
+ * Tokenizes and parses every top-level form in the file at `path` without
+ * evaluating anything, collecting a message for each form that fails to parse.
+ */
+fn check_file(path: &str) -> Result<(), Vec<String>> {
+    let source = fs::read_to_string(path)
+        .map_err(|error| vec![format!("Could not read {}: {}", path, error)])?;
+    let tokens = tokenize(&source).map_err(|error| vec![format!("{}: {}", path, error)])?;
+    let mut parser = Parser::new(tokens);
+    let mut errors = Vec::new();
+    let mut index = 0;
+
+    while !parser.is_empty() {
+        index += 1;
+
+        if let Err(error) = parser.parse() {
+            errors.push(format!("{}: form {}: {}", path, index, error));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/*
+ * The interactive read-eval-print loop. Every input line and the text printed
+ * for it can optionally be appended to a transcript writer.
+ */
+pub struct Repl {
+    interpreter: Interpreter,
+    log: Option<Box<dyn Write>>,
+    base: u32,
+    // The most recent results first, also bound to the parameters `*1`..`*3`.
+    history: Vec<LispExpr>,
+}
+
+const HISTORY_LENGTH: usize = 3;
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            interpreter: Interpreter::new(),
+            log: None,
+            base: 10,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn set_log(&mut self, log: Option<Box<dyn Write>>) {
+        self.log = log;
+    }
+
+    /*
+     * Evaluates a single line of input and returns the text to display for it.
+     * Lines starting with `:` are meta-commands and are not logged. Definitions
+     * display nothing, as their value is only a placeholder, and are left out
+     * of the result history.
+     */
+    pub fn eval_line(&mut self, line: &str) -> String {
+        if let Some(command) = line.trim().strip_prefix(':') {
+            return self.run_command(command);
+        }
+
+        let output = match tokenize(line).and_then(|tokens| Parser::new(tokens).parse()) {
+            Ok(ast) => {
+                let definition = is_definition(&ast);
+
+                match self.interpreter.evaluate(ast) {
+                    Ok(_) if definition => String::new(),
+                    Ok(result) => {
+                        let output = result.format_with_base(self.base);
+                        self.remember(result);
+                        output
+                    }
+                    Err(error) => format!("ERROR: {}", error),
+                }
+            }
+            Err(error) => format_parse_error(line, &error),
+        };
+
+        self.record(line, &output);
+
+        output
+    }
+
+    fn run_command(&mut self, command: &str) -> String {
+        // `:fmt` takes the rest of the line as source, spaces and all.
+        if let Some(source) = command.strip_prefix("fmt") {
+            if source.is_empty() || source.starts_with(char::is_whitespace) {
+                return format_source(source);
+            }
+        }
+
+        // So does `:load`, as paths may contain spaces. Quotes are optional.
+        if let Some(path) = command.strip_prefix("load") {
+            if path.is_empty() || path.starts_with(char::is_whitespace) {
+                return self.load(path.trim().trim_matches('"'));
+            }
+        }
+
+        let mut parts = command.split_whitespace();
+
+        match (parts.next(), parts.next()) {
+            (Some("log"), Some(path)) => match open_log(path) {
+                Ok(log) => {
+                    self.set_log(Some(log));
+                    format!("Logging transcript to {}", path)
+                }
+                Err(error) => format!("ERROR: {}", error),
+            },
+            (Some("reset"), None) => {
+                self.interpreter.reset();
+                self.history.clear();
+                "Interpreter reset".into()
+            }
+            (Some("log"), None) => {
+                self.set_log(None);
+                "Transcript logging disabled".into()
+            }
+            (Some("base"), Some(base)) => match base.parse() {
+                Ok(base @ 2) | Ok(base @ 8) | Ok(base @ 10) | Ok(base @ 16) => {
+                    self.base = base;
+                    format!("Printing integers in base {}", base)
+                }
+                _ => "ERROR: :base expects 2, 8, 10 or 16".into(),
+            },
+            (Some("profile"), Some("on")) => {
+                self.interpreter.set_profiling(true);
+                "Profiling enabled".into()
+            }
+            (Some("profile"), Some("off")) => {
+                self.interpreter.set_profiling(false);
+                "Profiling disabled".into()
+            }
+            (Some("timeout"), Some("off")) => {
+                self.interpreter.set_timeout(None);
+                "Timeout disabled".into()
+            }
+            (Some("timeout"), Some(millis)) => match millis.parse() {
+                Ok(millis) if millis > 0 => {
+                    self.interpreter
+                        .set_timeout(Some(Duration::from_millis(millis)));
+                    format!("Evaluations time out after {} ms", millis)
+                }
+                _ => "ERROR: :timeout expects a number of milliseconds or off".into(),
             },
-            LispExpr::Number(_) => Ok(ast),
-            LispExpr::Symbol(_) => Ok(ast),
+            (Some("profile"), Some("report")) => match self.interpreter.write_profile() {
+                Ok(total) => format!("{} calls profiled", total),
+                Err(error) => format!("ERROR: {}", error),
+            },
+            _ => format!("ERROR: Unknown command :{}", command),
+        }
+    }
+
+    /*
+     * Handles `:load`, evaluating a file's forms into the session. An error
+     * stops the load but leaves the definitions made before it in place.
+     */
+    fn load(&mut self, path: &str) -> String {
+        if path.is_empty() {
+            return "ERROR: :load expects a file path".into();
+        }
+
+        let result = fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {}: {}", path, error))
+            .and_then(|src| self.interpreter.evaluate_source(&src));
+
+        match result {
+            Ok(_) => format!("Loaded {}", path),
+            Err(error) => format!("ERROR: {}", error),
+        }
+    }
+
+    /*
+     * Shifts a successful result into the history, so `(get-param *1)` is the
+     * latest result, `(get-param *2)` the one before, and so on.
+     */
+    fn remember(&mut self, result: LispExpr) {
+        self.history.insert(0, result);
+        self.history.truncate(HISTORY_LENGTH);
+
+        for (index, value) in self.history.iter().enumerate() {
+            self.interpreter
+                .define_param(&format!("*{}", index + 1), value.clone());
+        }
+    }
+
+    fn record(&mut self, input: &str, output: &str) {
+        if let Some(ref mut log) = self.log {
+            let mut result = writeln!(log, "lisp> {}", input.trim_end());
+
+            if !output.is_empty() {
+                result = result.and_then(|_| writeln!(log, "{}", output));
+            }
+
+            let result = result.and_then(|_| log.flush());
+
+            if let Err(error) = result {
+                eprintln!(
+                    "ERROR: Could not write transcript, logging disabled: {}",
+                    error
+                );
+                self.log = None;
+            }
+        }
+    }
+
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+
+        if let Err(error) = self.run_with(&mut stdin.lock(), &mut io::stdout()) {
+            eprintln!("ERROR: {}", error);
+            process::exit(1);
+        }
+    }
+
+    /*
+     * Prompts for and evaluates lines from `input` until it reaches end of
+     * file, writing the prompts and results to `output`.
+     */
+    fn run_with(&mut self, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<(), String> {
+        loop {
+            writeln!(output, "lisp> ")
+                .map_err(|error| format!("Could not write output: {}", error))?;
+
+            let mut expr = String::new();
+
+            match input.read_line(&mut expr) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(error) => return Err(format!("Could not read from stdin: {}", error)),
+            }
+
+            let result = self.eval_line(&expr);
+
+            if !result.is_empty() {
+                writeln!(output, "{}", result)
+                    .map_err(|error| format!("Could not write output: {}", error))?;
+            }
+        }
+    }
+}
+
+/*
+ * Handles `:fmt`, printing an expression back on one line with canonical
+ * spacing and without evaluating it.
+ */
+fn format_source(source: &str) -> String {
+    match tokenize(source).and_then(|tokens| Parser::new(tokens).parse()) {
+        Ok(ast) => ast.to_string(),
+        Err(error) => format_parse_error(source, &error),
+    }
+}
+
+fn format_parse_error(source: &str, error: &ParseError) -> String {
+    match error.span {
+        Some(span) => format!("ERROR: {}\n{}", error, format_caret(source, span)),
+        None => format!("ERROR: {}", error),
+    }
+}
+
+/*
+ * Echoes the source line containing `span` with `^` markers under the spanned
+ * characters, like rustc. Whitespace before the span is copied from the line so
+ * tabs keep the markers aligned.
+ */
+fn format_caret(source: &str, span: Span) -> String {
+    let line_start = source[..span.start]
+        .rfind('\n')
+        .map_or(0, |index| index + 1);
+    let line_end = source[span.start..]
+        .find(['\r', '\n'])
+        .map_or(source.len(), |index| span.start + index);
+    let line = &source[line_start..line_end];
+
+    let padding: String = source[line_start..span.start]
+        .chars()
+        .map(|character| if character == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = source[span.start..span.end.min(line_end).max(span.start)]
+        .chars()
+        .count()
+        .max(1);
+
+    format!("{}\n{}{}", line, padding, "^".repeat(width))
+}
+
+fn open_log(path: &str) -> Result<Box<dyn Write>, String> {
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map(|file| Box::new(file) as Box<dyn Write>)
+        .map_err(|error| format!("Could not open {}: {}", path, error))
+}
+
+/*
+ * Evaluates a whole program against a single interpreter, returning the value
+ * of the final form, or `None` if the program has no forms or ends with a
+ * definition, whose value is only a placeholder.
+ */
+fn run_batch(src: &str) -> Result<Option<LispExpr>, String> {
+    let forms = tokenize(src)
+        .and_then(|tokens| Parser::new(tokens).parse_all())
+        .map_err(|error| error.to_string())?;
+
+    let ends_with_definition = forms.last().is_none_or(is_definition);
+    let result = Interpreter::new().evaluate_all(forms)?;
+
+    Ok(if ends_with_definition {
+        None
+    } else {
+        Some(result)
+    })
+}
+
+fn is_definition(form: &LispExpr) -> bool {
+    match form {
+        LispExpr::List(values) => matches!(
+            values.first(),
+            Some(LispExpr::Symbol(head))
+                if ["define-param", "define-values", "define-record"].contains(&head.as_str())
+        ),
+        _ => false,
+    }
+}
+
+/*
+ * Renders the parsed tree of every top-level form in `src` with one node per
+ * line, indented by depth, without evaluating anything.
+ */
+fn dump_ast(src: &str) -> Result<String, ParseError> {
+    let mut output = String::new();
+
+    for form in Parser::new(tokenize(src)?).parse_all()? {
+        write_ast_node(&form, 0, &mut output);
+    }
+
+    Ok(output.trim_end().into())
+}
+
+fn write_ast_node(expr: &LispExpr, depth: usize, output: &mut String) {
+    output.push_str(&"  ".repeat(depth));
+
+    match expr {
+        LispExpr::List(values) => {
+            output.push_str("List\n");
+
+            for value in values.iter() {
+                write_ast_node(value, depth + 1, output);
+            }
+        }
+        LispExpr::Number(number) => output.push_str(&format!("Number {}\n", number)),
+        LispExpr::Bool(value) => output.push_str(&format!("Bool {}\n", value)),
+        LispExpr::Symbol(symbol) => output.push_str(&format!("Symbol {}\n", symbol)),
+        _ => output.push_str(&format!("{}\n", expr)),
+    }
+}
+
+// Like `run_batch`, but reads the program from `input`, which must be UTF-8.
+fn run_batch_from(input: &mut dyn Read) -> Result<Option<LispExpr>, String> {
+    let mut src = String::new();
+
+    input
+        .read_to_string(&mut src)
+        .map_err(|error| format!("Could not read from stdin: {}", error))?;
+
+    run_batch(&src)
+}
+
+fn run_batch_stdin() {
+    match run_batch_from(&mut io::stdin()) {
+        Ok(Some(result)) => println!("{}", result.format_with_base(10)),
+        Ok(None) => {}
+        Err(error) => {
+            eprintln!("ERROR: {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("--check") => {
+            if args.len() < 2 {
+                eprintln!("Usage: simple-lisp --check <file>...");
+                process::exit(2);
+            }
+
+            let mut failed = false;
+
+            for path in &args[1..] {
+                if let Err(errors) = check_file(path) {
+                    failed = true;
+
+                    for error in errors {
+                        eprintln!("{}", error);
+                    }
+                }
+            }
+
+            if failed {
+                process::exit(1);
+            }
+        }
+        Some("--log") => {
+            let path = match args.get(1) {
+                Some(path) => path,
+                None => {
+                    eprintln!("Usage: simple-lisp --log <file>");
+                    process::exit(2);
+                }
+            };
+
+            let mut repl = Repl::new();
+
+            match open_log(path) {
+                Ok(log) => repl.set_log(Some(log)),
+                Err(error) => {
+                    eprintln!("ERROR: {}", error);
+                    process::exit(1);
+                }
+            }
+
+            repl.run();
+        }
+        Some("--ast") => {
+            let src = match args.get(1) {
+                Some(src) => src,
+                None => {
+                    eprintln!("Usage: simple-lisp --ast <expression>");
+                    process::exit(2);
+                }
+            };
+
+            match dump_ast(src) {
+                Ok(dump) => println!("{}", dump),
+                Err(error) => {
+                    match error.span {
+                        Some(span) => eprintln!("ERROR: {}\n{}", error, format_caret(src, span)),
+                        None => eprintln!("ERROR: {}", error),
+                    }
+
+                    process::exit(1);
+                }
+            }
+        }
+        Some("--version") => println!("simple-lisp {}", env!("CARGO_PKG_VERSION")),
+        Some("--batch") => run_batch_stdin(),
+        _ if !io::stdin().is_terminal() => run_batch_stdin(),
+        _ => Repl::new().run(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::path::PathBuf;
+
+    /*
+     * A writer whose contents stay readable after a clone of it has been handed
+     * to an interpreter or REPL.
+     */
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn parse(src: &str) -> LispExpr {
+        Parser::new(tokenize(src).unwrap()).parse().unwrap()
+    }
+
+    // Evaluates every form in `src`, returning the last value as lisp source or
+    // the first error message.
+    fn run_in(interpreter: &mut Interpreter, src: &str) -> String {
+        let forms = Parser::new(tokenize(src).unwrap()).parse_all().unwrap();
+        let mut result = LispExpr::nil();
+
+        for form in forms {
+            match interpreter.evaluate(form) {
+                Ok(value) => result = value,
+                Err(error) => return error,
+            }
+        }
+
+        result.to_string()
+    }
+
+    fn run(src: &str) -> String {
+        run_in(&mut Interpreter::new(), src)
+    }
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("simple-lisp-{}-{}", process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn forcing_a_promise_twice_evaluates_it_once() {
+        let ticks = Rc::new(Cell::new(0));
+        let counter = ticks.clone();
+        let mut interpreter = Interpreter::new();
+        interpreter.register("tick", move |_, _| {
+            counter.set(counter.get() + 1);
+            Ok(LispExpr::Number(counter.get()))
+        });
+
+        let promise = interpreter.evaluate(parse("(delay (tick))")).unwrap();
+        let force = LispExpr::list(vec![LispExpr::Symbol("force".into()), promise]);
+
+        assert!(matches!(
+            interpreter.evaluate(force.clone()),
+            Ok(LispExpr::Number(1))
+        ));
+        assert!(matches!(
+            interpreter.evaluate(force),
+            Ok(LispExpr::Number(1))
+        ));
+        assert_eq!(ticks.get(), 1);
+    }
+
+    #[test]
+    fn arity_errors_use_a_uniform_message() {
+        assert_eq!(run("(+)"), "+ expects at least 1 argument, got 0");
+        assert_eq!(run("(* 2)"), "* expects at least 2 arguments, got 1");
+        assert_eq!(
+            run("(delay 1 2)"),
+            "delay expects exactly 1 argument, got 2"
+        );
+    }
+
+    #[test]
+    fn registered_builtins_can_be_called() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("double", |_, args| {
+            check_arity("double", args, 1, Some(1))?;
+
+            match args[0] {
+                LispExpr::Number(number) => Ok(LispExpr::Number(number * 2)),
+                _ => Err("double expects a number".into()),
+            }
+        });
+
+        assert!(matches!(
+            interpreter.evaluate(parse("(double (+ 1 2))")),
+            Ok(LispExpr::Number(6))
+        ));
+        assert_eq!(
+            interpreter.evaluate(parse("(double)")).unwrap_err(),
+            "double expects exactly 1 argument, got 0"
+        );
+    }
+
+    #[test]
+    fn digits_followed_by_letters_are_an_invalid_number() {
+        let error = tokenize("1a").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid number literal: 1a at 0..2");
+
+        let error = tokenize("(+ 12ab 3)").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid number literal: 12ab at 3..7");
+
+        let tokens = tokenize("a1").unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].token_type, TokenType::Symbol("a1".into()));
+    }
+
+    #[test]
+    fn check_file_reports_only_malformed_forms() {
+        let path = temp_file("check.lisp", "(+ 1 2)\n(+ 1");
+        let result = check_file(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        let errors = result.unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("form 2: Missing closing right paren"));
+    }
+
+    #[test]
+    fn minus_negates_one_argument_and_subtracts_more() {
+        assert_eq!(run("(- 5)"), "-5");
+        assert_eq!(run("(- 5 3)"), "2");
+        assert_eq!(run("(- 10 3 2)"), "5");
+
+        let negate_min = LispExpr::list(vec![
+            LispExpr::Symbol("-".into()),
+            LispExpr::Number(i64::MIN),
+        ]);
+        assert_eq!(
+            Interpreter::new().evaluate(negate_min).unwrap_err(),
+            "Integer overflow in unary -"
+        );
+    }
+
+    #[test]
+    fn arithmetic_reports_overflow_and_division_by_zero() {
+        assert_eq!(run("(+ 1 2 3)"), "6");
+        assert_eq!(run("(* 2 3 4)"), "24");
+        assert_eq!(run("(/ 100 5 2)"), "10");
+        assert_eq!(run("(/ (- 7) 2)"), "-3");
+        assert_eq!(run("(+ 9223372036854775807 1)"), "Integer overflow in +");
+        assert_eq!(
+            run("(- (- 9223372036854775807) 2)"),
+            "Integer overflow in -"
+        );
+        assert_eq!(run("(* 4611686018427387904 2)"), "Integer overflow in *");
+        assert_eq!(
+            run("(/ (- (- 9223372036854775807) 1) (- 1))"),
+            "Integer overflow in /"
+        );
+        assert_eq!(run("(/ 1 0)"), "Division by zero in /");
+        assert_eq!(run("(/ 0 1 0)"), "Division by zero in /");
+    }
+
+    #[test]
+    fn the_transcript_records_each_evaluated_line() {
+        let log = SharedBuffer::default();
+        let mut repl = Repl::new();
+        repl.set_log(Some(Box::new(log.clone())));
+
+        repl.eval_line("(+ 1 2)\n");
+        repl.eval_line("(* 2 3)\n");
+        repl.eval_line(":nothing\n");
+
+        assert_eq!(
+            log.contents(),
+            "lisp> (+ 1 2)\nNumber(3)\nlisp> (* 2 3)\nNumber(6)\n"
+        );
+    }
+
+    #[test]
+    fn the_repl_stops_at_end_of_input() {
+        let log = SharedBuffer::default();
+        let mut output = Vec::new();
+        let mut repl = Repl::new();
+        repl.set_log(Some(Box::new(log.clone())));
+
+        let result = repl.run_with(&mut io::Cursor::new("(+ 1 2)\n(* 2 3)"), &mut output);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "lisp> \nNumber(3)\nlisp> \nNumber(6)\nlisp> \n"
+        );
+        assert_eq!(
+            log.contents(),
+            "lisp> (+ 1 2)\nNumber(3)\nlisp> (* 2 3)\nNumber(6)\n"
+        );
+    }
+
+    #[test]
+    fn the_repl_prints_nothing_for_definitions() {
+        let log = SharedBuffer::default();
+        let mut output = Vec::new();
+        let mut repl = Repl::new();
+        repl.set_log(Some(Box::new(log.clone())));
+
+        let input = "(+ 1 2)\n(define-param x 1)\n(define-record point (x))\n\
+                     (define-values (a b) (1 2))\n(get-param *1)\n";
+        repl.run_with(&mut io::Cursor::new(input), &mut output)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "lisp> \nNumber(3)\nlisp> \nlisp> \nlisp> \nlisp> \nNumber(3)\nlisp> \n"
+        );
+        assert_eq!(
+            log.contents(),
+            "lisp> (+ 1 2)\nNumber(3)\nlisp> (define-param x 1)\n\
+             lisp> (define-record point (x))\nlisp> (define-values (a b) (1 2))\n\
+             lisp> (get-param *1)\nNumber(3)\n"
+        );
+    }
+
+    #[test]
+    fn the_repl_reports_read_errors() {
+        struct FailingReader;
+
+        impl Read for FailingReader {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("disconnected"))
+            }
         }
+
+        let result = Repl::new().run_with(&mut io::BufReader::new(FailingReader), &mut Vec::new());
+
+        assert_eq!(
+            result,
+            Err("Could not read from stdin: disconnected".into())
+        );
     }
-}
 
-fn main() {
-    let interpreter = Interpreter::new();
+    #[test]
+    fn hash_tables_store_default_and_remove_values() {
+        assert_eq!(run("(hash-ref (hash-set! (make-hash) k 1) k)"), "1");
+        assert_eq!(run("(hash-ref (hash-set! (make-hash) 2 k) 2)"), "k");
+        assert_eq!(run("(hash-ref (make-hash) missing 0)"), "0");
+        assert_eq!(
+            run("(hash-ref (make-hash) missing)"),
+            "Key not found in hash: Symbol(\"missing\")"
+        );
+        assert_eq!(
+            run("(hash-ref (hash-remove! (hash-set! (make-hash) k 1) k) k 0)"),
+            "0"
+        );
+        assert_eq!(
+            run("(hash-set! (make-hash) (1 2) 3)"),
+            "Hash keys must be numbers or symbols"
+        );
+    }
+
+    #[test]
+    fn identity_returns_its_argument() {
+        assert_eq!(run("(identity 5)"), "5");
+        assert_eq!(run("(identity (+ 1 2))"), "3");
+        assert_eq!(
+            run("(identity 1 2)"),
+            "identity expects exactly 1 argument, got 2"
+        );
+    }
 
-    loop {
-        println!("lisp> ");
+    /*
+     * A linear congruential generator, so the fuzz tests below are repeatable
+     * without pulling in a dependency.
+     */
+    struct Lcg(u64);
 
-        let mut expr = String::new();
+    impl Lcg {
+        fn next(&mut self) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            self.0 >> 33
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
 
-        io::stdin()
-            .read_line(&mut expr)
-            .expect("Could not read from stdin.");
+        fn pick(&mut self, characters: &str) -> char {
+            let characters: Vec<char> = characters.chars().collect();
+            characters[self.below(characters.len())]
+        }
+    }
 
-        let tokens = tokenize(&expr);
-        let ast = Parser::new(tokens).parse();
+    // Half the fuzzed strings add characters the tokenizer rejects.
+    const FUZZ_ALPHABET: &str = "()[] \t\r\n0019azQ+-*/!?=.<>";
+    const FUZZ_JUNK: &str = "_#\"é\u{1F600}";
+    const SYMBOL_START: &str = "azQ+-*/!?=.<>";
 
-        match ast {
-            Ok(ast) => {
-                let result = interpreter.evaluate(ast);
+    fn random_expr(rng: &mut Lcg, depth: usize) -> LispExpr {
+        match rng.below(if depth < 4 { 4 } else { 3 }) {
+            0 => LispExpr::Number(rng.next() as i64),
+            1 => LispExpr::Bool(rng.below(2) == 0),
+            2 => {
+                let mut symbol = rng.pick(SYMBOL_START).to_string();
 
-                if result.is_ok() {
-                    println!("{:?}", result.unwrap());
-                } else {
-                    println!("ERROR: {}", result.unwrap_err());
+                for _ in 0..rng.below(4) {
+                    symbol.push(rng.pick("azQ+-*/!?=.<>019"));
                 }
+
+                LispExpr::Symbol(symbol)
             }
-            Err(error) => {
-                println!("ERROR: {}", error);
+            _ => LispExpr::list(
+                (0..rng.below(4))
+                    .map(|_| random_expr(rng, depth + 1))
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn fuzzed_input_never_panics_and_parsed_forms_round_trip() {
+        let mut rng = Lcg(116);
+        let mut parsed = 0;
+
+        for _ in 0..5000 {
+            let alphabet = if rng.below(2) == 0 {
+                FUZZ_ALPHABET.to_string()
+            } else {
+                format!("{}{}", FUZZ_ALPHABET, FUZZ_JUNK)
+            };
+            let source: String = (0..rng.below(40)).map(|_| rng.pick(&alphabet)).collect();
+            let strict = TokenizerOptions {
+                allow_leading_zeros: false,
+                allow_underscores: true,
+            };
+            let _ = tokenize_with(&source, strict);
+
+            let forms = match tokenize(&source).map(|tokens| Parser::new(tokens).parse_all()) {
+                Ok(Ok(forms)) => forms,
+                _ => continue,
+            };
+
+            for form in forms {
+                assert_eq!(parse(&form.to_string()), form, "source: {:?}", source);
+                parsed += 1;
             }
         }
+
+        // Most random strings don't balance their parens; make sure enough do.
+        assert!(parsed > 500);
+    }
+
+    #[test]
+    fn random_expressions_round_trip_through_display() {
+        let mut rng = Lcg(1);
+
+        for _ in 0..1000 {
+            let expr = random_expr(&mut rng, 0);
+
+            assert_eq!(parse(&expr.to_string()), expr);
+        }
+    }
+
+    #[test]
+    fn out_of_range_numbers_and_unknown_characters_are_errors() {
+        let error = tokenize("(+ 99999999999999999999 1)").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Number literal out of range: 99999999999999999999 at 3..23"
+        );
+
+        let error = tokenize("(+ 1 _)").unwrap_err();
+        assert_eq!(error.to_string(), "Unexpected character '_' at 5..6");
+        assert_eq!(tokenize("(+ 1 2").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn hash_tables_print_their_entries_and_stop_at_cycles() {
+        let mut repl = Repl::new();
+        repl.eval_line("(define-param h (hash-set! (hash-set! (make-hash) b 255) 1 (make-hash)))");
+
+        assert_eq!(
+            repl.eval_line("(get-param h)"),
+            "Hash({Number(1): Hash({}), Symbol(\"b\"): Number(255)})"
+        );
+
+        repl.eval_line("(define-record point (x))");
+        repl.eval_line(":base 16");
+        assert_eq!(
+            repl.eval_line("(hash-set! (get-param h) self (get-param h))"),
+            "Hash({Number(0x1): Hash({}), Symbol(\"b\"): Number(0xff), Symbol(\"self\"): Hash(..)})"
+        );
+        assert_eq!(
+            repl.eval_line("(make-point (get-param h))"),
+            "Record { type_name: \"point\", fields: [(\"x\", Hash({Number(0x1): Hash({}), \
+             Symbol(\"b\"): Number(0xff), Symbol(\"self\"): Hash(..)}))] }"
+        );
+    }
+
+    #[test]
+    fn hash_tables_holding_themselves_can_be_reported_and_compared() {
+        let mut interpreter = Interpreter::new();
+        run_in(
+            &mut interpreter,
+            "(define-param h (make-hash)) (hash-set! (get-param h) self (get-param h))",
+        );
+
+        assert_eq!(
+            run_in(&mut interpreter, "(+ (get-param h))"),
+            "+ expects a number, got Hash(..)"
+        );
+        assert_eq!(
+            run_in(
+                &mut interpreter,
+                "(assert-equal (get-param h) (get-param h))"
+            ),
+            "()"
+        );
+        assert_eq!(
+            run_in(&mut interpreter, "(assert-equal (get-param h) (make-hash))"),
+            "Assertion failed: expected Hash(..), got Hash(..)"
+        );
+    }
+
+    #[test]
+    fn number_predicates() {
+        assert_eq!(run("(zero? 0)"), "true");
+        assert_eq!(run("(zero? 1)"), "false");
+        assert_eq!(run("(positive? (- 1))"), "false");
+        assert_eq!(run("(negative? (- 1))"), "true");
+        assert_eq!(run("(even? 4)"), "true");
+        assert_eq!(run("(odd? 4)"), "false");
+        assert_eq!(run("(odd? 3)"), "true");
+        assert_eq!(
+            run("(zero? a)"),
+            "zero? expects a number, got Symbol(\"a\")"
+        );
+    }
+
+    #[test]
+    fn numbers_format_in_the_requested_base() {
+        let number = LispExpr::Number(255);
+
+        assert_eq!(number.format_with_base(10), "Number(255)");
+        assert_eq!(number.format_with_base(16), "Number(0xff)");
+        assert_eq!(number.format_with_base(2), "Number(0b11111111)");
+        assert_eq!(LispExpr::Number(-8).format_with_base(8), "Number(-0o10)");
+
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval_line(":base 16"), "Printing integers in base 16");
+        assert_eq!(repl.eval_line("(+ 10 5)"), "Number(0xf)");
+        assert_eq!(
+            repl.eval_line(":base 3"),
+            "ERROR: :base expects 2, 8, 10 or 16"
+        );
+        assert_eq!(repl.eval_line(":base 10"), "Printing integers in base 10");
+        assert_eq!(repl.eval_line("(+ 10 5)"), "Number(15)");
+    }
+
+    #[test]
+    fn take_and_drop_clamp_to_the_list_length() {
+        assert_eq!(run("(take (1 2 3 4 5) 2)"), "(1 2)");
+        assert_eq!(run("(drop (1 2 3 4 5) 2)"), "(3 4 5)");
+        assert_eq!(run("(take (1 2) 5)"), "(1 2)");
+        assert_eq!(run("(drop (1 2) 5)"), "()");
+        assert_eq!(
+            run("(take (1 2) (- 1))"),
+            "take expects a non-negative count, got -1"
+        );
+    }
+
+    #[test]
+    fn token_spans_match_their_source_positions() {
+        let source = "(+ 12 foo)";
+        let tokens = tokenize(source).unwrap();
+        let texts: Vec<&str> = tokens
+            .iter()
+            .map(|token| &source[token.span.start..token.span.end])
+            .collect();
+
+        assert_eq!(texts, ["(", "+", "12", "foo", ")"]);
+        assert_eq!(tokens[3].span, Span { start: 6, end: 9 });
+
+        let error = Parser::new(tokenize("(+ 1 (* 2 3)").unwrap())
+            .parse()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Missing closing right paren at 0..1");
+    }
+
+    #[test]
+    fn evaluate_all_shares_definitions_between_forms() {
+        let forms = Parser::new(
+            tokenize("(define-param a 1) (define-param b 2) (+ (get-param a) (get-param b))")
+                .unwrap(),
+        )
+        .parse_all()
+        .unwrap();
+
+        assert_eq!(
+            Interpreter::new().evaluate_all(forms),
+            Ok(LispExpr::Number(3))
+        );
+        assert_eq!(
+            Interpreter::new().evaluate_all(vec![parse("(+ 1 1)"), parse("(+ a)")]),
+            Err("Error in form 2: + expects a number, got Symbol(\"a\")".into())
+        );
+    }
+
+    #[test]
+    fn assertions_return_nil_or_describe_the_mismatch() {
+        assert_eq!(run("(assert (zero? 0))"), "()");
+        assert_eq!(run("(assert (zero? 1))"), "Assertion failed");
+        assert_eq!(run("(assert-equal (+ 1 1) 2)"), "()");
+        assert_eq!(run("(assert-equal (take (1 2 3) 2) (1 2))"), "()");
+        assert_eq!(
+            run("(assert-equal (+ 1 1) 3)"),
+            "Assertion failed: expected Number(3), got Number(2)"
+        );
+    }
+
+    #[test]
+    fn batch_mode_returns_the_final_value() {
+        assert_eq!(
+            run_batch("(define-param x 4)\n(square (get-param x))\n"),
+            Ok(Some(LispExpr::Number(16)))
+        );
+        assert_eq!(run_batch("  \n"), Ok(None));
+        assert_eq!(
+            run_batch("(+ 1 2)\n(+ a)\n"),
+            Err("Error in form 2: + expects a number, got Symbol(\"a\")".into())
+        );
+        assert_eq!(
+            run_batch("(+ 1"),
+            Err("Missing closing right paren at 0..1".into())
+        );
+        assert_eq!(run_batch("(define-param x 4)"), Ok(None));
+        assert_eq!(run_batch("(define-values (a b) (1 2))"), Ok(None));
+        assert_eq!(run_batch("(define-record point (x y))"), Ok(None));
+        assert_eq!(
+            run_batch("(define-record point (x y))\n(point-y (make-point 1 2))"),
+            Ok(Some(LispExpr::Number(2)))
+        );
+        assert_eq!(run_batch("()"), Ok(Some(LispExpr::nil())));
+    }
+
+    #[test]
+    fn batch_input_must_be_utf8() {
+        assert_eq!(
+            run_batch_from(&mut "(+ 1 2)".as_bytes()),
+            Ok(Some(LispExpr::Number(3)))
+        );
+        assert_eq!(
+            run_batch_from(&mut &b"(+ 1 \xff)"[..]),
+            Err("Could not read from stdin: stream did not contain valid UTF-8".into())
+        );
+    }
+
+    #[test]
+    fn max_by_and_min_by_compare_keys_not_elements() {
+        assert_eq!(run("(min-by - (1 3 2))"), "3");
+        assert_eq!(run("(max-by - (1 3 2))"), "1");
+        assert_eq!(run("(max-by identity (1 3 2))"), "3");
+        assert_eq!(run("(max-by - ())"), "max-by of empty list");
+        assert_eq!(
+            run("(min-by nothing (1 2))"),
+            "Symbol(\"nothing\") is not a procedure"
+        );
+    }
+
+    #[test]
+    fn the_tokenizer_iterator_matches_tokenize() {
+        let source = "(+ 1 (* 2 3))";
+        let streamed: Vec<Token> = Tokenizer::new(source).map(Result::unwrap).collect();
+        let collected = tokenize(source).unwrap();
+
+        assert_eq!(streamed.len(), collected.len());
+
+        for (streamed, collected) in streamed.iter().zip(&collected) {
+            assert_eq!(streamed.token_type, collected.token_type);
+            assert_eq!(streamed.span, collected.span);
+        }
+
+        let mut tokenizer = Tokenizer::new("(1 #)");
+        assert!(tokenizer.next().unwrap().is_ok());
+        assert!(tokenizer.next().unwrap().is_ok());
+        assert_eq!(
+            tokenizer.next().unwrap().unwrap_err().message,
+            "Unexpected character '#'"
+        );
+        assert!(tokenizer.next().is_none());
+    }
+
+    #[test]
+    fn list_and_pair_predicates() {
+        assert_eq!(run("(list? (1 2 3))"), "true");
+        assert_eq!(run("(pair? (1 2 3))"), "true");
+        assert_eq!(run("(list? 1)"), "false");
+        assert_eq!(run("(pair? 1)"), "false");
+        assert_eq!(run("(list? ())"), "true");
+        assert_eq!(run("(pair? ())"), "false");
+    }
+
+    #[test]
+    fn long_evaluations_time_out() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("spin", |interpreter, _| loop {
+            interpreter.evaluate(LispExpr::Number(0))?;
+        });
+        interpreter.set_timeout(Some(Duration::from_millis(20)));
+
+        let start = Instant::now();
+        assert_eq!(
+            interpreter.evaluate(parse("(spin)")).unwrap_err(),
+            "Evaluation timed out"
+        );
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn the_repl_sets_and_clears_the_timeout() {
+        let mut repl = Repl::new();
+        repl.interpreter.register("spin", |interpreter, _| loop {
+            interpreter.evaluate(LispExpr::Number(0))?;
+        });
+
+        assert_eq!(
+            repl.eval_line(":timeout 20"),
+            "Evaluations time out after 20 ms"
+        );
+        assert_eq!(repl.eval_line("(spin)"), "ERROR: Evaluation timed out");
+        assert_eq!(repl.eval_line(":timeout off"), "Timeout disabled");
+        assert_eq!(repl.interpreter.timeout, None);
+        assert_eq!(
+            repl.eval_line(":timeout 0"),
+            "ERROR: :timeout expects a number of milliseconds or off"
+        );
+        assert_eq!(
+            repl.eval_line(":timeout soon"),
+            "ERROR: :timeout expects a number of milliseconds or off"
+        );
+    }
+
+    #[test]
+    fn pretty_breaks_long_lists_across_lines() {
+        let expr =
+            parse("(a (b 1111111111 2222222222 3333333333 4444444444 5555555555 6666666666) c)");
+
+        assert_eq!(
+            expr.pretty(0),
+            "(a\n  (b\n    1111111111\n    2222222222\n    3333333333\n    4444444444\n    5555555555\n    6666666666)\n  c)"
+        );
+        assert_eq!(parse("(a (b c))").pretty(0), "(a (b c))");
+    }
+
+    #[test]
+    fn parameterize_restores_the_value_even_on_error() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(
+            run_in(
+                &mut interpreter,
+                "(define-param p 10) (parameterize ((p 20)) (get-param p))"
+            ),
+            "20"
+        );
+        assert_eq!(
+            run_in(&mut interpreter, "(parameterize ((p 20)) (assert false))"),
+            "Assertion failed"
+        );
+        assert_eq!(run_in(&mut interpreter, "(get-param p)"), "10");
+    }
+
+    #[test]
+    fn reset_drops_definitions_but_keeps_builtins() {
+        let mut interpreter = Interpreter::new();
+        run_in(
+            &mut interpreter,
+            "(define-param x 1) (define-record point (x y))",
+        );
+
+        interpreter.reset();
+
+        assert_eq!(
+            run_in(&mut interpreter, "(get-param x)"),
+            "Undefined parameter: x"
+        );
+        assert_eq!(run_in(&mut interpreter, "(procedure? make-point)"), "false");
+        assert_eq!(run_in(&mut interpreter, "(+ 1 2)"), "3");
+    }
+
+    #[test]
+    fn zip_truncates_and_unzip_reverses_it() {
+        assert_eq!(run("(zip (1 2 3) (a b c))"), "((1 a) (2 b) (3 c))");
+        assert_eq!(run("(zip (1 2 3) (a b))"), "((1 a) (2 b))");
+        assert_eq!(run("(unzip ((1 a) (2 b)))"), "((1 2) (a b))");
+    }
+
+    #[test]
+    fn records_have_a_constructor_predicate_and_accessors() {
+        let mut interpreter = Interpreter::new();
+        run_in(&mut interpreter, "(define-record point (x y))");
+
+        assert_eq!(
+            run_in(&mut interpreter, "(make-point 1 2)"),
+            "#<point x=1 y=2>"
+        );
+        assert_eq!(
+            run_in(&mut interpreter, "(point? (make-point 1 2))"),
+            "true"
+        );
+        assert_eq!(run_in(&mut interpreter, "(point? 1)"), "false");
+        assert_eq!(run_in(&mut interpreter, "(point-y (make-point 1 2))"), "2");
+        assert_eq!(
+            run_in(&mut interpreter, "(point-x 1)"),
+            "point-x expects a point record"
+        );
+    }
+
+    #[test]
+    fn records_cannot_replace_builtins() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(
+            run_in(&mut interpreter, "(define-record list (x))"),
+            "define-record list would redefine the builtin make-list"
+        );
+        assert_eq!(
+            run_in(&mut interpreter, "(define-record hash (keys))"),
+            "define-record hash would redefine the builtin make-hash"
+        );
+        assert_eq!(
+            run_in(
+                &mut interpreter,
+                "(define-record point (x)) (define-record point (y))"
+            ),
+            "define-record point would redefine the builtin make-point"
+        );
+        assert_eq!(run_in(&mut interpreter, "(list? (make-list 1))"), "true");
+        assert_eq!(
+            run_in(&mut interpreter, "(procedure? stream-cell)"),
+            "false"
+        );
+        assert_eq!(run_in(&mut interpreter, "(procedure? point-y)"), "false");
+    }
+
+    #[test]
+    fn folds_differ_in_associativity() {
+        assert_eq!(run("(fold-left - 0 (1 2 3))"), "-6");
+        assert_eq!(run("(fold-right - 0 (1 2 3))"), "2");
+    }
+
+    #[test]
+    fn type_predicates() {
+        assert_eq!(run("(boolean? true)"), "true");
+        assert_eq!(run("(boolean? 0)"), "false");
+        assert_eq!(run("(procedure? take)"), "true");
+        assert_eq!(run("(procedure? delay)"), "false");
+    }
+
+    #[test]
+    fn parse_errors_point_a_caret_at_the_column() {
+        let source = "(+ 1a 2)";
+        let error = tokenize(source).unwrap_err();
+
+        assert_eq!(
+            format_parse_error(source, &error),
+            "ERROR: Invalid number literal: 1a at 3..5\n(+ 1a 2)\n   ^^"
+        );
+
+        let source = "(+ 1\n   2a)";
+        let error = tokenize(source).unwrap_err();
+
+        assert_eq!(format_caret(source, error.span.unwrap()), "   2a)\n   ^^");
+    }
+
+    #[test]
+    fn iota_takes_a_count_start_and_step() {
+        assert_eq!(run("(iota 5)"), "(0 1 2 3 4)");
+        assert_eq!(run("(iota 5 1)"), "(1 2 3 4 5)");
+        assert_eq!(run("(iota 5 0 2)"), "(0 2 4 6 8)");
+        assert_eq!(
+            run("(iota (- 1))"),
+            "iota expects a non-negative count, got -1"
+        );
+        assert_eq!(
+            run("(iota 9223372036854775807)"),
+            "iota count must be at most 16777216, got 9223372036854775807"
+        );
+    }
+
+    #[test]
+    fn eqv_compares_identity_and_equals_compares_numbers() {
+        assert_eq!(run("(eqv? 2 2)"), "true");
+        assert_eq!(run("(eqv? a a)"), "true");
+        assert_eq!(run("(eqv? 2 a)"), "false");
+        assert_eq!(run("(eqv? (1) (1))"), "false");
+        assert_eq!(run("(eqv? () ())"), "true");
+        assert_eq!(run("(= 2 2 2)"), "true");
+        assert_eq!(run("(= 2 3)"), "false");
+        assert_eq!(run("(= a a)"), "= expects a number, got Symbol(\"a\")");
+    }
+
+    #[test]
+    fn records_are_eqv_with_eqv_fields() {
+        let mut interpreter = Interpreter::new();
+        run_in(
+            &mut interpreter,
+            "(define-record point (x y)) (define-record size (x y)) \
+             (define-param p (make-point 1 2))",
+        );
+
+        assert_eq!(
+            run_in(&mut interpreter, "(eqv? (get-param p) (get-param p))"),
+            "true"
+        );
+        assert_eq!(
+            run_in(&mut interpreter, "(eqv? (make-point 1 2) (make-point 1 2))"),
+            "true"
+        );
+        assert_eq!(
+            run_in(&mut interpreter, "(eqv? (make-point 1 2) (make-point 1 3))"),
+            "false"
+        );
+        assert_eq!(
+            run_in(&mut interpreter, "(eqv? (make-point 1 2) (make-size 1 2))"),
+            "false"
+        );
+        assert_eq!(
+            run_in(
+                &mut interpreter,
+                "(eqv? (make-point (1) 2) (make-point (1) 2))"
+            ),
+            "false"
+        );
+        assert_eq!(
+            run_in(
+                &mut interpreter,
+                "(assv (make-point 1 2) (zip (make-list 1 (get-param p)) (found)))"
+            ),
+            "(#<point x=1 y=2> found)"
+        );
+    }
+
+    #[test]
+    fn association_lists() {
+        assert_eq!(run("(del-assoc b ((a 1) (b 2) (c 3)))"), "((a 1) (c 3))");
+        assert_eq!(run("(del-assoc d ((a 1) (b 2)))"), "((a 1) (b 2))");
+        assert_eq!(run("(assq b ((a 1) (b 2)))"), "(b 2)");
+        assert_eq!(run("(assq (b) (((b) 2)))"), "false");
+        assert_eq!(run("(assoc (b) (((b) 2)))"), "((b) 2)");
+    }
+
+    #[test]
+    fn dump_ast_indents_nested_lists() {
+        assert_eq!(
+            dump_ast("(+ 1 (* 2 3))").unwrap(),
+            "List\n  Symbol +\n  Number 1\n  List\n    Symbol *\n    Number 2\n    Number 3"
+        );
+    }
+
+    #[test]
+    fn count_and_remove_apply_the_predicate() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register("big?", |_, args| {
+            Ok(LispExpr::Bool(as_number("big?", &args[0])? > 2))
+        });
+
+        assert_eq!(run_in(&mut interpreter, "(count big? (1 2 3 4))"), "2");
+        assert_eq!(run_in(&mut interpreter, "(remove big? (1 2 3 4))"), "(1 2)");
+        assert_eq!(run_in(&mut interpreter, "(count even? ())"), "0");
+        assert_eq!(run_in(&mut interpreter, "(remove even? ())"), "()");
+    }
+
+    #[test]
+    fn cloned_lists_share_storage_and_compare_structurally() {
+        let list = LispExpr::list(vec![LispExpr::Number(1), LispExpr::Number(2)]);
+        let copy = list.clone();
+
+        match (&list, &copy) {
+            (LispExpr::List(left), LispExpr::List(right)) => assert!(Rc::ptr_eq(left, right)),
+            _ => unreachable!(),
+        }
+
+        assert_eq!(list, parse("(1 2)"));
+        assert!(!is_eqv(&list, &parse("(1 2)")));
+    }
+
+    #[test]
+    fn leading_zeros_are_optional() {
+        let strict = TokenizerOptions {
+            allow_leading_zeros: false,
+            ..TokenizerOptions::default()
+        };
+
+        assert_eq!(tokenize("007").unwrap()[0].token_type, TokenType::Number(7));
+        assert_eq!(
+            tokenize_with("007", strict).unwrap_err().message,
+            "Leading zeros are not allowed: 007"
+        );
+        assert_eq!(
+            tokenize_with("0", strict).unwrap()[0].token_type,
+            TokenType::Number(0)
+        );
+    }
+
+    #[test]
+    fn arguments_are_evaluated_left_to_right() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let entries = log.clone();
+        let mut interpreter = Interpreter::new();
+        interpreter.register("log", move |_, args| {
+            entries.borrow_mut().push(args[0].to_string());
+            Ok(args[0].clone())
+        });
+
+        let mut logged = |src| {
+            let result = run_in(&mut interpreter, src);
+            (result, log.replace(Vec::new()))
+        };
+
+        assert_eq!(
+            logged("(+ (log 1) (log 2))"),
+            ("3".into(), vec!["1".into(), "2".into()])
+        );
+        assert_eq!(
+            logged("(zip (log (1)) (log (2)))"),
+            ("((1 2))".into(), vec!["(1)".into(), "(2)".into()])
+        );
+        assert_eq!(
+            logged("(+ (log 1) (+ (log 2) (log 3)) (... (log (4))))").1,
+            ["1", "2", "3", "(4)"]
+        );
+        assert_eq!(
+            logged("(+ (log 1) (assert false) (log 3))"),
+            ("Assertion failed".into(), vec!["1".into()])
+        );
+    }
+
+    #[test]
+    fn integer_square_roots() {
+        assert_eq!(run("(isqrt 16)"), "4");
+        assert_eq!(run("(isqrt 17)"), "4");
+        assert_eq!(run("(isqrt 9223372036854775807)"), "3037000499");
+        assert_eq!(run("(exact-integer-sqrt 17)"), "(4 1)");
+        assert_eq!(
+            run("(isqrt (- 1))"),
+            "isqrt expects a non-negative number, got -1"
+        );
+    }
+
+    #[test]
+    fn splices_pass_list_elements_as_arguments() {
+        assert_eq!(run("(+ 1 (... (iota 3)) 10)"), "14");
+        assert_eq!(
+            run("(define-record point (x y)) (make-point (... (iota 2 5)))"),
+            "#<point x=5 y=6>"
+        );
+        assert_eq!(run("(+ (... 1))"), "... expects a list, got Number(1)");
+    }
+
+    #[test]
+    fn help_describes_arity() {
+        let output = SharedBuffer::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Box::new(output.clone()));
+
+        assert_eq!(run_in(&mut interpreter, "(help take)"), "()");
+        assert!(output
+            .contents()
+            .starts_with("take: takes exactly 2 arguments."));
+        assert_eq!(
+            run_in(&mut interpreter, "(help nothing)"),
+            "help: nothing is not a builtin"
+        );
+    }
+
+    #[test]
+    fn help_describes_records_and_registered_builtins() {
+        let output = SharedBuffer::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Box::new(output.clone()));
+        interpreter.register("plain", |_, _| Ok(LispExpr::nil()));
+        interpreter.register_documented("twice", 1, Some(1), "Doubles a number.", |_, args| {
+            Ok(LispExpr::Number(as_number("twice", &args[0])? * 2))
+        });
+
+        run_in(&mut interpreter, "(define-record point (x y))");
+        run_in(&mut interpreter, "(help make-point)");
+        run_in(&mut interpreter, "(help point?)");
+        run_in(&mut interpreter, "(help point-y)");
+        run_in(&mut interpreter, "(help twice)");
+        run_in(&mut interpreter, "(help plain)");
+
+        assert_eq!(
+            output.contents(),
+            "make-point: takes exactly 2 arguments. Constructs a point record.\n\
+             point?: takes exactly 1 argument. Tests whether a value is a point record.\n\
+             point-y: takes exactly 1 argument. Returns the y field of a point record.\n\
+             twice: takes exactly 1 argument. Doubles a number.\n\
+             plain: builtin, no documentation available\n"
+        );
+
+        // Replacing a documented builtin drops its now stale documentation.
+        interpreter.register("twice", |_, _| Ok(LispExpr::nil()));
+        run_in(&mut interpreter, "(help twice)");
+        assert!(output
+            .contents()
+            .ends_with("twice: builtin, no documentation available\n"));
+    }
+
+    #[test]
+    fn documented_arity_is_checked_before_the_builtin_runs() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_documented(
+            "first",
+            1,
+            Some(2),
+            "Returns its first argument.",
+            |_, args| Ok(args[0].clone()),
+        );
+
+        assert_eq!(run_in(&mut interpreter, "(first 1 2)"), "1");
+        assert_eq!(
+            run_in(&mut interpreter, "(first)"),
+            "first expects at least 1 argument, got 0"
+        );
+        assert_eq!(
+            run_in(&mut interpreter, "(map first (list 1) (list 2) (list 3))"),
+            "first expects at most 2 arguments, got 3"
+        );
+        assert_eq!(
+            run_in(
+                &mut interpreter,
+                "(define-record point (x y)) (make-point 1)"
+            ),
+            "make-point expects exactly 2 arguments, got 1"
+        );
+        assert_eq!(
+            run_in(&mut interpreter, "(point-x)"),
+            "point-x expects exactly 1 argument, got 0"
+        );
+        assert_eq!(
+            run("(version 1)"),
+            "version expects exactly 0 arguments, got 1"
+        );
+        assert_eq!(
+            run("(hash-ref)"),
+            "hash-ref expects at least 2 arguments, got 0"
+        );
+    }
+
+    #[test]
+    fn make_list_repeats_the_fill() {
+        assert_eq!(run("(make-list 3 0)"), "(0 0 0)");
+        assert_eq!(run("(make-list 2)"), "(() ())");
+        assert_eq!(
+            run("(make-list (- 1))"),
+            "make-list expects a non-negative count, got -1"
+        );
+        assert_eq!(
+            run("(make-list 9223372036854775807)"),
+            "make-list count must be at most 16777216, got 9223372036854775807"
+        );
+    }
+
+    // Renders each token with its span, like "Number(1)@3..4".
+    fn token_list(source: &str) -> Vec<String> {
+        tokenize(source)
+            .unwrap()
+            .iter()
+            .map(|token| format!("{:?}@{}", token.token_type, token.span))
+            .collect()
+    }
+
+    #[test]
+    fn carriage_returns_tabs_and_leading_whitespace_are_skipped() {
+        assert_eq!(
+            token_list("(+ 1 2)\r\n"),
+            [
+                "LeftParen@0..1",
+                "Symbol(\"+\")@1..2",
+                "Number(1)@3..4",
+                "Number(2)@5..6",
+                "RightParen@6..7"
+            ]
+        );
+        assert_eq!(
+            token_list("\t(+\t1\t2)\r\n"),
+            [
+                "LeftParen@1..2",
+                "Symbol(\"+\")@2..3",
+                "Number(1)@4..5",
+                "Number(2)@6..7",
+                "RightParen@7..8"
+            ]
+        );
+        assert_eq!(token_list("   foo\r\n"), ["Symbol(\"foo\")@3..6"]);
+        assert_eq!(token_list("abc\r"), ["Symbol(\"abc\")@0..3"]);
+        assert_eq!(token_list("(iota\r\n3)").len(), 4);
+        assert!(token_list("\r\n").is_empty());
+        assert!(token_list(" \t \r\n").is_empty());
+        assert_eq!(
+            tokenize("1a\r\n").unwrap_err().to_string(),
+            "Invalid number literal: 1a at 0..2"
+        );
+        assert_eq!(Repl::new().eval_line("\t(+ 1 2)\r\n"), "Number(3)");
+    }
+
+    #[test]
+    fn last_returns_the_final_element() {
+        assert_eq!(run("(last (1 2 3))"), "3");
+        assert_eq!(run("(last (1))"), "1");
+        assert_eq!(run("(last-pair (1 2 3))"), "(3)");
+        assert_eq!(run("(last-pair (1))"), "(1)");
+        assert_eq!(run("(last ())"), "last of empty list");
+        assert_eq!(run("(last-pair ())"), "last of empty list");
+    }
+
+    #[test]
+    fn flatten_removes_all_nesting() {
+        assert_eq!(run("(flatten (1 (2 (3 4)) 5))"), "(1 2 3 4 5)");
+        assert_eq!(run("(flatten (1 2 3))"), "(1 2 3)");
+        assert_eq!(run("(flatten (1 () (()) 2))"), "(1 2)");
+    }
+
+    #[test]
+    fn deeply_nested_values_can_be_flattened_printed_and_dropped() {
+        let mut repl = Repl::new();
+        repl.eval_line("(define-param deep (fold-left zip (1) (make-list 20000 (1))))");
+
+        assert_eq!(
+            repl.eval_line("(last (flatten (get-param deep)))"),
+            "Number(1)"
+        );
+        assert!(repl
+            .eval_line("(get-param deep)")
+            .starts_with("List([List([List(["));
+        assert!(repl
+            .eval_line("(+ (get-param deep))")
+            .starts_with("ERROR: + expects a number, got List([List([List(["));
+
+        let printed = run("(last (fold-left zip (1) (make-list 200000 (1))))");
+        assert!(printed.starts_with("((((") && printed.ends_with(" 1) 1) 1)"));
+    }
+
+    #[test]
+    fn map_walks_several_lists_together() {
+        assert_eq!(run("(map + (1 2 3) (10 20 30))"), "(11 22 33)");
+        assert_eq!(run("(map + (1 2) (10 20) (100 200))"), "(111 222)");
+        assert_eq!(run("(map + (1 2 3) (10))"), "(11)");
+    }
+
+    #[test]
+    fn history_tracks_the_latest_results() {
+        let mut repl = Repl::new();
+
+        for line in &["1", "(+ 1 1)", "(+ 1 2)"] {
+            repl.eval_line(line);
+        }
+
+        let history: Vec<String> = ["*1", "*2", "*3"]
+            .iter()
+            .map(|name| repl.interpreter.params[*name].last().unwrap().to_string())
+            .collect();
+
+        assert_eq!(history, ["3", "2", "1"]);
+    }
+
+    #[test]
+    fn for_each_visits_every_element_and_returns_nil() {
+        let output = SharedBuffer::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Box::new(output.clone()));
+
+        assert_eq!(run_in(&mut interpreter, "(for-each println (1 2 3))"), "()");
+        assert_eq!(output.contents(), "1\n2\n3\n");
+    }
+
+    #[test]
+    fn fmt_normalizes_spacing() {
+        assert_eq!(format_source("(  +   1    2 )"), "(+ 1 2)");
+        assert_eq!(Repl::new().eval_line(":fmt [a   (b)]"), "(a (b))");
+    }
+
+    #[test]
+    fn hash_keys_and_values_correspond() {
+        let program = "(define-param h (hash-set! (hash-set! (make-hash) b 2) a 1))";
+
+        assert_eq!(
+            run(&format!("{} (hash-keys (get-param h))", program)),
+            "(a b)"
+        );
+        assert_eq!(
+            run(&format!("{} (hash-values (get-param h))", program)),
+            "(1 2)"
+        );
+        assert_eq!(
+            run(&format!("{} (hash->list (get-param h))", program)),
+            "((a 1) (b 2))"
+        );
+        assert_eq!(
+            run(&format!(
+                "{} (hash-map + (hash-set! (make-hash) 1 2))",
+                program
+            )),
+            "(3)"
+        );
+    }
+
+    #[test]
+    fn infinite_streams_are_taken_lazily() {
+        assert_eq!(
+            run("(define-param ones (cons-stream 1 (get-param ones))) (stream-take (get-param ones) 5)"),
+            "(1 1 1 1 1)"
+        );
+        assert_eq!(run("(stream-car (cons-stream 1 (assert false)))"), "1");
+        assert_eq!(
+            run("(stream-cdr (cons-stream 1 (assert false)))"),
+            "Assertion failed"
+        );
+    }
+
+    #[test]
+    fn argument_helpers_accept_their_type_and_name_the_caller_otherwise() {
+        let numbers = [LispExpr::Number(1), LispExpr::Number(2)];
+        let list = LispExpr::list(numbers.to_vec());
+        let hash = LispExpr::Hash(Rc::new(RefCell::new(HashMap::new())));
+
+        assert_eq!(as_number("f", &LispExpr::Number(3)), Ok(3));
+        assert_eq!(
+            as_number("f", &parse("a")),
+            Err("f expects a number, got Symbol(\"a\")".into())
+        );
+        assert_eq!(as_numbers("f", &numbers), Ok(vec![1, 2]));
+        assert_eq!(
+            as_numbers("f", &[LispExpr::Number(1), LispExpr::nil()]),
+            Err("f expects a number, got List([])".into())
+        );
+        assert_eq!(as_list("f", &list), Ok(&numbers[..]));
+        assert_eq!(
+            as_list("f", &LispExpr::Number(1)),
+            Err("f expects a list, got Number(1)".into())
+        );
+        assert!(as_hash("f", &hash).unwrap().borrow().is_empty());
+        assert_eq!(
+            as_hash("f", &LispExpr::Number(1)).unwrap_err(),
+            "f expects a hash table"
+        );
+    }
+
+    #[test]
+    fn profiling_counts_each_call() {
+        let output = SharedBuffer::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Box::new(output.clone()));
+        interpreter.set_profiling(true);
+
+        run_in(&mut interpreter, "(+ (+ 1 2) 3)");
+
+        assert_eq!(interpreter.profile.as_ref().unwrap()["+"], 2);
+        assert_eq!(interpreter.write_profile(), Ok(2));
+        assert_eq!(output.contents(), "       2  +\n");
+    }
+
+    #[test]
+    fn self_forcing_promises_hit_the_call_depth_limit() {
+        assert_eq!(
+            run("(define-param p (delay (force (get-param p)))) (force (get-param p))"),
+            "Maximum call depth exceeded"
+        );
+    }
+
+    #[test]
+    fn deep_nesting_fails_without_overflowing_the_stack() {
+        let nested = |depth| format!("{}1{}", "(+ 1 ".repeat(depth), ")".repeat(depth));
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(
+            run_in(&mut interpreter, &nested(MAX_EXPRESSION_DEPTH)),
+            (MAX_EXPRESSION_DEPTH + 1).to_string()
+        );
+        assert_eq!(
+            run_in(&mut interpreter, &nested(MAX_EXPRESSION_DEPTH + 1)),
+            "Maximum expression depth exceeded"
+        );
+        assert_eq!(run_in(&mut interpreter, "(+ 1 (+ 1 1))"), "3");
+
+        let source = nested(20000);
+        let error = Parser::new(tokenize(&source).unwrap()).parse().unwrap_err();
+        let start = 5 * MAX_PARSE_DEPTH;
+
+        assert_eq!(
+            error.to_string(),
+            format!("Maximum nesting depth exceeded at {}..{}", start, start + 1)
+        );
+
+        let path = temp_file("deep.lisp", &format!("{}\n(+ 1\n(+ 2 3)", source));
+        let result = check_file(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        let errors = result.unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("form 1: Maximum nesting depth exceeded"));
+        assert!(errors[1].contains("form 2: Missing closing right paren"));
+    }
+
+    #[test]
+    fn list_copy_is_equal_but_not_eqv() {
+        let mut interpreter = Interpreter::new();
+        run_in(&mut interpreter, "(define-param l (1 2 3))");
+
+        assert_eq!(
+            run_in(
+                &mut interpreter,
+                "(assert-equal (list-copy (get-param l)) (get-param l))"
+            ),
+            "()"
+        );
+        assert_eq!(
+            run_in(
+                &mut interpreter,
+                "(eqv? (list-copy (get-param l)) (get-param l))"
+            ),
+            "false"
+        );
+        assert_eq!(
+            run_in(&mut interpreter, "(eqv? (get-param l) (get-param l))"),
+            "true"
+        );
+    }
+
+    #[test]
+    fn division_operators_round_in_their_direction() {
+        assert_eq!(run("(floor/ 7 2)"), "(3 1)");
+        assert_eq!(run("(truncate/ 7 2)"), "(3 1)");
+        assert_eq!(run("(floor/ (- 7) 2)"), "(-4 1)");
+        assert_eq!(run("(truncate/ (- 7) 2)"), "(-3 -1)");
+        assert_eq!(run("(floor/ 7 (- 2))"), "(-4 -1)");
+        assert_eq!(run("(truncate/ 7 (- 2))"), "(-3 1)");
+        assert_eq!(run("(ceiling/ 7 2)"), "(4 -1)");
+        assert_eq!(run("(round/ 5 2)"), "(2 1)");
+        assert_eq!(run("(round/ 7 2)"), "(4 -1)");
+        assert_eq!(run("(floor/ 1 0)"), "Division by zero in floor/");
+    }
+
+    #[test]
+    fn malformed_special_forms_are_rejected() {
+        assert_eq!(
+            run("(define-record p (x y x))"),
+            "define-record field x is listed more than once"
+        );
+        assert_eq!(
+            run("(define-record p (x 1))"),
+            "define-record fields must be symbols"
+        );
+        assert_eq!(
+            run("(define-param p 1) (parameterize ((p)) (get-param p))"),
+            "parameterize binding must be (param value)"
+        );
+        assert_eq!(
+            run("(define-param p 1) (parameterize (p) (get-param p))"),
+            "parameterize binding must be (param value)"
+        );
+        assert_eq!(
+            run("(case 1 ())"),
+            "case clause must be ((datum ...) body ...)"
+        );
+        assert_eq!(
+            run("(case 1 (1 yes))"),
+            "case clause must start with a list of datums"
+        );
+        assert_eq!(
+            run("(case 1 (else no) ((1) yes))"),
+            "case else clause must be last"
+        );
+    }
+
+    #[test]
+    fn version_matches_the_package() {
+        assert_eq!(
+            run("(version)"),
+            format!(
+                "({} {} {})",
+                env!("CARGO_PKG_VERSION_MAJOR"),
+                env!("CARGO_PKG_VERSION_MINOR"),
+                env!("CARGO_PKG_VERSION_PATCH")
+            )
+        );
+    }
+
+    #[test]
+    fn println_separates_arguments_with_spaces() {
+        let output = SharedBuffer::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_output(Box::new(output.clone()));
+
+        run_in(&mut interpreter, "(println 1 2 3) (print a (b c))");
+
+        assert_eq!(output.contents(), "1 2 3\na (b c)");
+    }
+
+    #[test]
+    fn define_values_binds_each_value() {
+        let mut interpreter = Interpreter::new();
+
+        run_in(&mut interpreter, "(define-values (q r) (floor/ 7 2))");
+
+        assert_eq!(run_in(&mut interpreter, "(get-param q)"), "3");
+        assert_eq!(run_in(&mut interpreter, "(get-param r)"), "1");
+        assert_eq!(
+            run_in(&mut interpreter, "(define-values (a b c) (floor/ 7 2))"),
+            "define-values expects 3 values, got 2"
+        );
+    }
+
+    #[test]
+    fn square_brackets_delimit_lists() {
+        assert_eq!(parse("(let ([x 1]) x)"), parse("(let ((x 1)) x)"));
+
+        let strict = ParserOptions {
+            require_matching_brackets: true,
+        };
+        let error = Parser::with_options(tokenize("[+ 1 2)").unwrap(), strict)
+            .parse()
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Mismatched closing delimiter, expected ']' at 6..7"
+        );
+        assert!(Parser::new(tokenize("[+ 1 2)").unwrap()).parse().is_ok());
+    }
+
+    #[test]
+    fn square_and_cube_check_for_overflow() {
+        assert_eq!(run("(square 5)"), "25");
+        assert_eq!(run("(cube 3)"), "27");
+        assert_eq!(run("(square 3037000499)"), "9223372030926249001");
+        assert_eq!(run("(square 3037000500)"), "Integer overflow in square");
+    }
+
+    #[test]
+    fn load_evaluates_a_file_into_the_session() {
+        let path = temp_file(
+            "load.lisp",
+            "(define-param x 5)\n(define-record point (x y))\n",
+        );
+        let mut repl = Repl::new();
+
+        let message = repl.eval_line(&format!(":load {}", path.display()));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(message, format!("Loaded {}", path.display()));
+        assert_eq!(repl.eval_line("(get-param x)"), "Number(5)");
+        assert_eq!(repl.eval_line("(point? (make-point 1 2))"), "Bool(true)");
+    }
+
+    #[test]
+    fn list_index_finds_the_first_match() {
+        assert_eq!(run("(list-index even? (1 3 5 6 7))"), "3");
+        assert_eq!(run("(list-index even? (1 3))"), "false");
+        assert_eq!(run("(list-index even? ())"), "false");
+    }
+
+    #[test]
+    fn case_matches_datums_with_eqv() {
+        assert_eq!(run("(case 2 ((1 2) yes) (else no))"), "yes");
+        assert_eq!(run("(case 3 ((1 2) yes) (else no))"), "no");
+        assert_eq!(run("(case a ((a) yes))"), "yes");
+        assert_eq!(run("(case (1) (((1)) yes) (else no))"), "no");
+        assert_eq!(run("(case 3 ((1 2) yes))"), "()");
+    }
+
+    #[test]
+    fn forced_streams_that_refer_to_themselves_can_be_shown_and_compared() {
+        let mut interpreter = Interpreter::new();
+        run_in(
+            &mut interpreter,
+            "(define-param ones (cons-stream 1 (get-param ones))) (stream-take (get-param ones) 2)",
+        );
+
+        assert_eq!(
+            run_in(&mut interpreter, "(+ (get-param ones))"),
+            "+ expects a number, got List([Number(1), Promise(RefCell { value: Forced(..) })])"
+        );
+        assert_eq!(
+            run_in(
+                &mut interpreter,
+                "(assert-equal (get-param ones) (get-param ones))"
+            ),
+            "()"
+        );
+        assert_eq!(
+            run_in(
+                &mut interpreter,
+                "(eqv? (cons-stream 1 2) (cons-stream 1 2))"
+            ),
+            "false"
+        );
+        assert_eq!(
+            run_in(&mut interpreter, "(assert-equal (delay 1) (delay 1))"),
+            "Assertion failed: expected Promise(RefCell { value: Delayed(Number(1)) }), \
+             got Promise(RefCell { value: Delayed(Number(1)) })"
+        );
     }
 }